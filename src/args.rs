@@ -0,0 +1,39 @@
+/// The values after every `flag` in `args`, in order. A flag with nothing
+/// after it is an error.
+pub fn flag_values<'a>(args: &'a [String], flag: &str) -> Result<Vec<&'a str>, String> {
+    args.iter()
+        .enumerate()
+        .filter(|(_, arg)| *arg == flag)
+        .map(|(ix, _)| {
+            args.get(ix + 1)
+                .map(String::as_str)
+                .ok_or_else(|| format!("missing value for {}", flag))
+        })
+        .collect()
+}
+
+/// The value after the first `flag` in `args`, if there is one.
+pub fn flag_value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>, String> {
+    Ok(flag_values(args, flag)?.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let args = ["day17", "--goal", "1,2", "--k", "3", "--goal", "right-edge"].map(String::from);
+        assert_eq!(flag_value(&args, "--k"), Ok(Some("3")));
+        assert_eq!(flag_value(&args, "--goal"), Ok(Some("1,2")));
+        assert_eq!(flag_values(&args, "--goal"), Ok(vec!["1,2", "right-edge"]));
+        assert_eq!(flag_value(&args, "--start"), Ok(None));
+        assert_eq!(flag_values(&args, "--start"), Ok(vec![]));
+
+        let args = ["day17", "--k"].map(String::from);
+        assert_eq!(
+            flag_value(&args, "--k"),
+            Err("missing value for --k".to_string())
+        );
+    }
+}
@@ -22,7 +22,7 @@ impl Card {
         };
 
         Card {
-            id: card.split(' ').last().unwrap().parse::<usize>().unwrap(),
+            id: card.split(' ').next_back().unwrap().parse::<usize>().unwrap(),
             winners: parse(winners),
             my_numbers: parse(mine),
         }
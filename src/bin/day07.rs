@@ -0,0 +1,222 @@
+use aoc2023::error::ParseError;
+use aoc2023::string;
+use itertools::Itertools;
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+
+/// How cards are ranked and hands classified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rules {
+    Standard,
+    /// `J` is the weakest card, but stands in for whichever card makes the
+    /// best hand.
+    JokersWild,
+}
+
+impl Rules {
+    /// The cards from weakest to strongest.
+    fn card_order(self) -> &'static [u8] {
+        match self {
+            Rules::Standard => b"23456789TJQKA",
+            Rules::JokersWild => b"J23456789TQKA",
+        }
+    }
+
+    fn card_rank(self, card: u8) -> usize {
+        self.card_order()
+            .iter()
+            .position(|c| *c == card)
+            .expect("cards are checked when parsing")
+    }
+}
+
+/// Hand types, from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum HandType {
+    HighCard,
+    OnePair,
+    TwoPair,
+    ThreeOfAKind,
+    FullHouse,
+    FourOfAKind,
+    FiveOfAKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hand {
+    cards: [u8; 5],
+    bid: u64,
+}
+
+impl FromStr for Hand {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cards, bid) = string::words(s)
+            .collect_tuple()
+            .ok_or_else(|| ParseError(format!("expected cards and a bid: {}", s)))?;
+
+        let cards: [u8; 5] = cards
+            .as_bytes()
+            .try_into()
+            .map_err(|_| ParseError(format!("expected 5 cards: {}", cards)))?;
+        if let Some(card) = cards
+            .iter()
+            .find(|card| !Rules::Standard.card_order().contains(card))
+        {
+            return Err(ParseError(format!("unknown card '{}'", *card as char)));
+        }
+
+        let bid = bid
+            .parse()
+            .map_err(|_| ParseError(format!("unparsable bid: {}", bid)))?;
+
+        Ok(Hand { cards, bid })
+    }
+}
+
+impl Hand {
+    fn classify(&self, rules: Rules) -> HandType {
+        let jokers = match rules {
+            Rules::Standard => 0,
+            Rules::JokersWild => self.cards.iter().filter(|c| **c == b'J').count(),
+        };
+        let mut counts = self
+            .cards
+            .iter()
+            .filter(|c| rules == Rules::Standard || **c != b'J')
+            .counts()
+            .into_values()
+            .sorted()
+            .rev()
+            .collect_vec();
+
+        // Jokers are always best spent on the most common card.
+        match counts.first_mut() {
+            Some(count) => *count += jokers,
+            None => counts.push(jokers),
+        }
+
+        match counts[..] {
+            [5] => HandType::FiveOfAKind,
+            [4, ..] => HandType::FourOfAKind,
+            [3, 2] => HandType::FullHouse,
+            [3, ..] => HandType::ThreeOfAKind,
+            [2, 2, ..] => HandType::TwoPair,
+            [2, ..] => HandType::OnePair,
+            _ => HandType::HighCard,
+        }
+    }
+
+    /// What hands are ranked by: their type, then each card in turn.
+    fn strength(&self, rules: Rules) -> (HandType, [usize; 5]) {
+        (
+            self.classify(rules),
+            self.cards.map(|card| rules.card_rank(card)),
+        )
+    }
+}
+
+fn total_winnings(hands: &[Hand], rules: Rules) -> u64 {
+    hands
+        .iter()
+        .sorted_by_cached_key(|hand| hand.strength(rules))
+        .zip(1..)
+        .map(|(hand, rank)| hand.bid * rank)
+        .sum()
+}
+
+fn parse_input(input: &str) -> Result<Vec<Hand>, ParseError> {
+    input.lines().map(str::parse).collect()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("inputs/day07.txt").expect("Could not read input");
+
+    let hands = parse_input(&contents)?;
+
+    println!("Part 1: {}", total_winnings(&hands, Rules::Standard));
+    println!("Part 2: {}", total_winnings(&hands, Rules::JokersWild));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+32T3K 765
+T55J5 684
+KK677 28
+KTJJT 220
+QQQJA 483
+";
+
+    fn hand(cards: &str) -> Hand {
+        format!("{} 1", cards).parse().unwrap()
+    }
+
+    #[test]
+    fn example() {
+        let hands = parse_input(EXAMPLE).unwrap();
+        assert_eq!(total_winnings(&hands, Rules::Standard), 6440);
+        assert_eq!(total_winnings(&hands, Rules::JokersWild), 5905);
+    }
+
+    #[test]
+    fn classification() {
+        use HandType::*;
+
+        let standard = [
+            "AAAAA", "AA8AA", "23332", "TTT98", "23432", "A23A4", "23456",
+        ]
+        .map(|cards| hand(cards).classify(Rules::Standard));
+        assert_eq!(
+            standard,
+            [
+                FiveOfAKind,
+                FourOfAKind,
+                FullHouse,
+                ThreeOfAKind,
+                TwoPair,
+                OnePair,
+                HighCard
+            ]
+        );
+
+        let wild = ["JJJJJ", "QJJQ2", "KTJJT", "T55J5", "2345J", "2233J"]
+            .map(|cards| hand(cards).classify(Rules::JokersWild));
+        assert_eq!(
+            wild,
+            [
+                FiveOfAKind,
+                FourOfAKind,
+                FourOfAKind,
+                FourOfAKind,
+                OnePair,
+                FullHouse
+            ]
+        );
+    }
+
+    #[test]
+    fn ranking() {
+        // Same type, so the first differing card decides.
+        assert!(hand("33332").strength(Rules::Standard) > hand("2AAAA").strength(Rules::Standard));
+        assert!(hand("KK677").strength(Rules::Standard) > hand("KTJJT").strength(Rules::Standard));
+        // A joker is weaker than a 2 when breaking ties.
+        assert!(
+            hand("JKKK2").strength(Rules::JokersWild) < hand("QQQQ2").strength(Rules::JokersWild)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("32T3K".parse::<Hand>().is_err());
+        assert!("32T3 765".parse::<Hand>().is_err());
+        assert!("32T3X 765".parse::<Hand>().is_err());
+        assert!("32T3K bid".parse::<Hand>().is_err());
+    }
+}
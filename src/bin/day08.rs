@@ -0,0 +1,295 @@
+use aoc2023::error::ParseError;
+use aoc2023::math::crt;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+type Label = str;
+
+/// The instructions, as `0` for left and `1` for right, and the nodes with
+/// their left and right neighbours by index.
+struct Network<'a> {
+    instructions: Vec<usize>,
+    labels: Vec<&'a Label>,
+    nodes: Vec<[usize; 2]>,
+}
+
+impl<'a> Network<'a> {
+    fn from_input(input: &'a str) -> Result<Self, ParseError> {
+        let mut lines = input.lines();
+        let instructions = lines
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| match c {
+                'L' => Ok(0),
+                'R' => Ok(1),
+                _ => Err(ParseError(format!("unknown instruction '{}'", c))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if instructions.is_empty() {
+            return Err(ParseError("no instructions".to_string()));
+        }
+
+        let edges = lines
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.split_once(" = (")
+                    .and_then(|(label, rest)| {
+                        Some((label, rest.strip_suffix(')')?.split_once(", ")?))
+                    })
+                    .map(|(label, (left, right))| (label, [left, right]))
+                    .ok_or_else(|| ParseError(format!("unparsable line: {}", line)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let index: HashMap<&Label, usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(ix, (label, _))| (*label, ix))
+            .collect();
+        let nodes = edges
+            .iter()
+            .map(|(_, next)| {
+                next.iter()
+                    .map(|label| {
+                        index
+                            .get(label)
+                            .copied()
+                            .ok_or_else(|| ParseError(format!("undefined node {}", label)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|next| [next[0], next[1]])
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Network {
+            instructions,
+            labels: edges.iter().map(|(label, _)| *label).collect(),
+            nodes,
+        })
+    }
+
+    fn find(&self, label: &Label) -> Option<usize> {
+        self.labels.iter().position(|l| *l == label)
+    }
+
+    /// Steps from `from` to the first node `is_end` accepts, if any within
+    /// one full loop of the walk.
+    fn steps(&self, from: usize, is_end: impl Fn(&Label) -> bool) -> Option<i64> {
+        let walk = self.walk(from, &is_end);
+        walk.prefix_hits.first().or(walk.loop_hits.first()).copied()
+    }
+
+    /// Follows the instructions from `from` until a (node, instruction)
+    /// state repeats, noting the steps at which `is_end` nodes are reached.
+    fn walk(&self, from: usize, is_end: impl Fn(&Label) -> bool) -> Walk {
+        let mut seen = HashMap::new();
+        let mut hits = vec![];
+        let mut node = from;
+
+        for step in 0.. {
+            let instruction = step as usize % self.instructions.len();
+            if let Some(&loop_start) = seen.get(&(node, instruction)) {
+                let (prefix_hits, loop_hits) = hits.iter().partition(|hit| **hit < loop_start);
+                return Walk {
+                    loop_start,
+                    period: step - loop_start,
+                    prefix_hits,
+                    loop_hits,
+                };
+            }
+            seen.insert((node, instruction), step);
+            if is_end(self.labels[node]) {
+                hits.push(step);
+            }
+            node = self.nodes[node][self.instructions[instruction]];
+        }
+        unreachable!()
+    }
+}
+
+/// The steps at which one walker stands on an end node: some before it
+/// starts looping, then `loop_hits` again every `period` steps.
+#[derive(Debug, PartialEq, Eq)]
+struct Walk {
+    loop_start: i64,
+    period: i64,
+    prefix_hits: Vec<i64>,
+    loop_hits: Vec<i64>,
+}
+
+impl Walk {
+    fn hits(&self, step: i64) -> bool {
+        if step < self.loop_start {
+            return self.prefix_hits.contains(&step);
+        }
+        self.loop_hits
+            .iter()
+            .any(|hit| step >= *hit && (step - hit) % self.period == 0)
+    }
+}
+
+/// The first step at which every walk is on an end node. Steps before some
+/// walk starts looping are checked directly; after that, each choice of one
+/// looping hit per walk gives a system of congruences for the CRT.
+fn first_common_hit(walks: &[Walk]) -> Option<i64> {
+    let early = walks
+        .iter()
+        .flat_map(|walk| &walk.prefix_hits)
+        .filter(|step| walks.iter().all(|walk| walk.hits(**step)))
+        .min();
+    if let Some(step) = early {
+        return Some(*step);
+    }
+
+    walks
+        .iter()
+        .map(|walk| walk.loop_hits.iter().map(move |hit| (*hit, walk.period)))
+        .multi_cartesian_product()
+        .filter_map(|hits| {
+            let (residue, modulus) = hits
+                .iter()
+                .try_fold((0, 1), |acc, (hit, period)| crt(acc, (*hit, *period)))?;
+            let earliest = hits.iter().map(|(hit, _)| *hit).max()?;
+            let rounds = (earliest - residue + modulus - 1)
+                .div_euclid(modulus)
+                .max(0);
+            Some(residue + rounds * modulus)
+        })
+        .min()
+}
+
+fn ghost_steps(network: &Network) -> Option<i64> {
+    let walks = (0..network.labels.len())
+        .filter(|node| network.labels[*node].ends_with('A'))
+        .map(|node| network.walk(node, |label| label.ends_with('Z')))
+        .collect_vec();
+    if walks.is_empty() {
+        return None;
+    }
+    first_common_hit(&walks)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("inputs/day08.txt").expect("Could not read input");
+
+    let network = Network::from_input(&contents)?;
+
+    match network.find("AAA") {
+        Some(start) => match network.steps(start, |label| label == "ZZZ") {
+            Some(steps) => println!("Part 1: {}", steps),
+            None => println!("Part 1: ZZZ is never reached"),
+        },
+        None => println!("Part 1: no node AAA"),
+    }
+    match ghost_steps(&network) {
+        Some(steps) => println!("Part 2: {}", steps),
+        None => println!("Part 2: the ghosts never all reach an end"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part_1(input: &str) -> Option<i64> {
+        let network = Network::from_input(input).unwrap();
+        network.steps(network.find("AAA")?, |label| label == "ZZZ")
+    }
+
+    #[test]
+    fn examples() {
+        let first = "\
+RL
+
+AAA = (BBB, CCC)
+BBB = (DDD, EEE)
+CCC = (ZZZ, GGG)
+DDD = (DDD, DDD)
+EEE = (EEE, EEE)
+GGG = (GGG, GGG)
+ZZZ = (ZZZ, ZZZ)
+";
+        assert_eq!(part_1(first), Some(2));
+
+        let second = "\
+LLR
+
+AAA = (BBB, BBB)
+BBB = (AAA, ZZZ)
+ZZZ = (ZZZ, ZZZ)
+";
+        assert_eq!(part_1(second), Some(6));
+
+        let ghosts = "\
+LR
+
+11A = (11B, XXX)
+11B = (XXX, 11Z)
+11Z = (11B, XXX)
+22A = (22B, XXX)
+22B = (22C, 22C)
+22C = (22Z, 22Z)
+22Z = (22B, 22B)
+XXX = (XXX, XXX)
+";
+        let network = Network::from_input(ghosts).unwrap();
+        assert_eq!(ghost_steps(&network), Some(6));
+    }
+
+    #[test]
+    fn unclean_cycles() {
+        // The first ghost is on 11Z at every even step from 2, the second
+        // at steps 1, 4, 7, ... The LCM of the periods would say 6.
+        let input = "\
+L
+
+11A = (11B, 11B)
+11B = (11Z, 11Z)
+11Z = (11B, 11B)
+22A = (22Z, 22Z)
+22Z = (22B, 22B)
+22B = (22C, 22C)
+22C = (22Z, 22Z)
+";
+        let network = Network::from_input(input).unwrap();
+        let first = network.walk(0, |label| label.ends_with('Z'));
+        assert_eq!(
+            first,
+            Walk {
+                loop_start: 1,
+                period: 2,
+                prefix_hits: vec![],
+                loop_hits: vec![2],
+            }
+        );
+        assert_eq!(ghost_steps(&network), Some(4));
+
+        // A hit before the loop starts counts too: the second ghost is on
+        // 22Z only at step 2.
+        let early = input
+            .replace("22A = (22Z, 22Z)", "22A = (22B, 22B)")
+            .replace("22B = (22C, 22C)", "22B = (22Z, 22Z)")
+            .replace("22Z = (22B, 22B)", "22Z = (22C, 22C)")
+            .replace("22C = (22Z, 22Z)", "22C = (22C, 22C)");
+        let network = Network::from_input(&early).unwrap();
+        assert_eq!(ghost_steps(&network), Some(2));
+
+        // Even against odd steps: never together.
+        let never = input.replace("22B = (22C, 22C)", "22B = (22Z, 22Z)");
+        let network = Network::from_input(&never).unwrap();
+        assert_eq!(ghost_steps(&network), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Network::from_input("LX\n\nAAA = (AAA, AAA)\n").is_err());
+        assert!(Network::from_input("L\n\nAAA = (AAA, BBB)\n").is_err());
+        assert!(Network::from_input("L\n\nAAA -> AAA\n").is_err());
+        assert!(Network::from_input("").is_err());
+    }
+}
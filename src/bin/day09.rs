@@ -30,7 +30,7 @@ fn next_history(seq: &[i32]) -> i32 {
         seqs[row].push(last_diff);
     }
 
-    return *seqs.first().unwrap().last().unwrap();
+    *seqs.first().unwrap().last().unwrap()
 }
 
 fn first_history(seq: &[i32]) -> i32 {
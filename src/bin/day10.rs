@@ -0,0 +1,390 @@
+use aoc2023::error::ParseError;
+use std::error::Error;
+use std::{env, fs};
+
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
+struct Pos2 {
+    y: i32,
+    x: i32,
+}
+
+impl Pos2 {
+    fn step(&self, dir: Dir) -> Pos2 {
+        let Pos2 { y, x } = dir.to_diff();
+        Pos2 {
+            y: self.y + y,
+            x: self.x + x,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
+enum Dir {
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+impl Dir {
+    const ALL: [Dir; 4] = [Dir::Up, Dir::Right, Dir::Down, Dir::Left];
+
+    fn opposite(self) -> Dir {
+        Dir::ALL[(self as usize + 2) % 4]
+    }
+
+    fn to_diff(self) -> Pos2 {
+        match self {
+            Dir::Up => Pos2 { y: -1, x: 0 },
+            Dir::Down => Pos2 { y: 1, x: 0 },
+            Dir::Left => Pos2 { y: 0, x: -1 },
+            Dir::Right => Pos2 { y: 0, x: 1 },
+        }
+    }
+}
+
+/// The pipe shapes and the two directions each one connects, in `Dir`
+/// order.
+const PIPES: [(char, [Dir; 2]); 6] = [
+    ('|', [Dir::Up, Dir::Down]),
+    ('-', [Dir::Right, Dir::Left]),
+    ('L', [Dir::Up, Dir::Right]),
+    ('J', [Dir::Up, Dir::Left]),
+    ('7', [Dir::Down, Dir::Left]),
+    ('F', [Dir::Right, Dir::Down]),
+];
+
+fn connections(tile: char) -> Option<[Dir; 2]> {
+    PIPES
+        .iter()
+        .find(|(pipe, _)| *pipe == tile)
+        .map(|(_, dirs)| *dirs)
+}
+
+fn pipe_of(mut dirs: [Dir; 2]) -> char {
+    dirs.sort_by_key(|dir| *dir as u8);
+    PIPES
+        .iter()
+        .find(|(_, pipe_dirs)| *pipe_dirs == dirs)
+        .map(|(pipe, _)| *pipe)
+        .expect("any two distinct directions make a pipe")
+}
+
+fn box_drawing(pipe: char) -> char {
+    match pipe {
+        '|' => '│',
+        '-' => '─',
+        'L' => '└',
+        'J' => '┘',
+        '7' => '┐',
+        'F' => '┌',
+        _ => pipe,
+    }
+}
+
+struct Grid {
+    tiles: Vec<Vec<char>>,
+    start: Pos2,
+}
+
+impl Grid {
+    fn from_input(input: &str) -> Result<Grid, ParseError> {
+        let tiles: Vec<Vec<char>> = input.lines().map(|line| line.chars().collect()).collect();
+
+        let mut starts = vec![];
+        for (y, row) in tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                match tile {
+                    'S' => starts.push(Pos2 {
+                        y: y as i32,
+                        x: x as i32,
+                    }),
+                    '.' => {}
+                    _ if connections(*tile).is_some() => {}
+                    _ => {
+                        return Err(ParseError(format!(
+                            "line {}, column {}: unknown tile '{}'",
+                            y + 1,
+                            x + 1,
+                            tile
+                        )))
+                    }
+                }
+            }
+        }
+
+        match starts[..] {
+            [start] => Ok(Grid { tiles, start }),
+            [] => Err(ParseError("no start tile".to_string())),
+            _ => Err(ParseError(format!("{} start tiles", starts.len()))),
+        }
+    }
+
+    /// The tile at `pos`, or ground outside the grid.
+    fn get(&self, pos: Pos2) -> char {
+        if pos.y < 0 || pos.x < 0 {
+            return '.';
+        }
+        self.tiles
+            .get(pos.y as usize)
+            .and_then(|row| row.get(pos.x as usize))
+            .copied()
+            .unwrap_or('.')
+    }
+
+    /// Follows the pipes out of `S` in each direction until one leads back.
+    fn find_loop(&self) -> Option<Loop> {
+        'dirs: for first in Dir::ALL {
+            let mut tiles = vec![self.start];
+            let (mut pos, mut dir) = (self.start, first);
+            loop {
+                pos = pos.step(dir);
+                if pos == self.start {
+                    return Some(Loop {
+                        tiles,
+                        start_pipe: pipe_of([first, dir.opposite()]),
+                    });
+                }
+                match connections(self.get(pos)) {
+                    Some([a, b]) if a == dir.opposite() => dir = b,
+                    Some([a, b]) if b == dir.opposite() => dir = a,
+                    _ => continue 'dirs,
+                }
+                tiles.push(pos);
+            }
+        }
+        None
+    }
+
+    /// The pipe at `pos`, with `S` replaced by its inferred shape.
+    fn pipe(&self, pos: Pos2, main_loop: &Loop) -> char {
+        if pos == self.start {
+            main_loop.start_pipe
+        } else {
+            self.get(pos)
+        }
+    }
+
+    fn on_loop(&self, main_loop: &Loop) -> Vec<Vec<bool>> {
+        let mut on_loop = self
+            .tiles
+            .iter()
+            .map(|row| vec![false; row.len()])
+            .collect::<Vec<_>>();
+        for pos in &main_loop.tiles {
+            on_loop[pos.y as usize][pos.x as usize] = true;
+        }
+        on_loop
+    }
+
+    /// Scans each row left to right: every loop tile with a pipe going up
+    /// crosses the loop's boundary, so tiles after an odd number of those
+    /// are inside.
+    fn enclosed_by_parity(&self, main_loop: &Loop) -> Vec<Pos2> {
+        let on_loop = self.on_loop(main_loop);
+        let mut enclosed = vec![];
+
+        for (y, row) in on_loop.iter().enumerate() {
+            let mut inside = false;
+            for (x, on_loop) in row.iter().enumerate() {
+                let pos = Pos2 {
+                    y: y as i32,
+                    x: x as i32,
+                };
+                if *on_loop {
+                    let dirs = connections(self.pipe(pos, main_loop)).unwrap();
+                    if dirs.contains(&Dir::Up) {
+                        inside = !inside;
+                    }
+                } else if inside {
+                    enclosed.push(pos);
+                }
+            }
+        }
+
+        enclosed
+    }
+
+    /// Renders the loop in box-drawing characters, enclosed tiles as `I`
+    /// and everything else as `.`.
+    fn render(&self, main_loop: &Loop) -> String {
+        let on_loop = self.on_loop(main_loop);
+        let mut out = self
+            .tiles
+            .iter()
+            .map(|row| vec!['.'; row.len()])
+            .collect::<Vec<_>>();
+        for pos in self.enclosed_by_parity(main_loop) {
+            out[pos.y as usize][pos.x as usize] = 'I';
+        }
+        for (y, row) in on_loop.iter().enumerate() {
+            for (x, _) in row.iter().enumerate().filter(|(_, on_loop)| **on_loop) {
+                let pos = Pos2 {
+                    y: y as i32,
+                    x: x as i32,
+                };
+                out[y][x] = box_drawing(self.pipe(pos, main_loop));
+            }
+        }
+
+        out.iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
+    }
+}
+
+/// The tiles of the main loop in order, starting at `S`, and the pipe `S`
+/// stands for.
+struct Loop {
+    tiles: Vec<Pos2>,
+    start_pipe: char,
+}
+
+impl Loop {
+    fn farthest(&self) -> usize {
+        self.tiles.len() / 2
+    }
+
+    /// Twice the area from the shoelace formula gives, through Pick's
+    /// theorem `A = i + b/2 - 1`, the number of tiles `i` strictly inside.
+    fn enclosed_by_pick(&self) -> usize {
+        let twice_area = self
+            .tiles
+            .iter()
+            .zip(self.tiles.iter().cycle().skip(1))
+            .map(|(a, b)| a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64)
+            .sum::<i64>()
+            .unsigned_abs() as usize;
+        (twice_area + 2 - self.tiles.len()) / 2
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("inputs/day10.txt").expect("Could not read input");
+    let args: Vec<String> = env::args().collect();
+
+    let grid = Grid::from_input(&contents)?;
+    let main_loop = grid.find_loop().ok_or("no loop through the start tile")?;
+
+    if args.iter().any(|arg| arg == "--render") {
+        print!("{}", grid.render(&main_loop));
+    }
+
+    println!("Part 1: {}", main_loop.farthest());
+
+    let by_parity = grid.enclosed_by_parity(&main_loop).len();
+    let by_pick = main_loop.enclosed_by_pick();
+    if by_parity != by_pick {
+        return Err(format!(
+            "enclosed tiles disagree: {} by parity, {} by Pick's theorem",
+            by_parity, by_pick
+        )
+        .into());
+    }
+    println!("Part 2: {}", by_parity);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(input: &str) -> (usize, usize, usize, char) {
+        let grid = Grid::from_input(input).unwrap();
+        let main_loop = grid.find_loop().unwrap();
+        (
+            main_loop.farthest(),
+            grid.enclosed_by_parity(&main_loop).len(),
+            main_loop.enclosed_by_pick(),
+            main_loop.start_pipe,
+        )
+    }
+
+    #[test]
+    fn farthest() {
+        let square = "\
+-L|F7
+7S-7|
+L|7||
+-L-J|
+L|-JF
+";
+        assert_eq!(solve(square), (4, 1, 1, 'F'));
+
+        let complex = "\
+7-F7-
+.FJ|7
+SJLL7
+|F--J
+LJ.LJ
+";
+        assert_eq!(solve(complex), (8, 1, 1, 'F'));
+    }
+
+    #[test]
+    fn enclosed() {
+        let simple = "\
+...........
+.S-------7.
+.|F-----7|.
+.||.....||.
+.||.....||.
+.|L-7.F-J|.
+.|..|.|..|.
+.L--J.L--J.
+...........
+";
+        assert_eq!(solve(simple).1, 4);
+        assert_eq!(solve(simple).2, 4);
+
+        let larger = "\
+.F----7F7F7F7F-7....
+.|F--7||||||||FJ....
+.||.FJ||||||||L7....
+FJL7L7LJLJ||LJ.L-7..
+L--J.L7...LJS7F-7L7.
+....F-J..F7FJ|L7L7L7
+....L7.F7||L7|.L7L7|
+.....|FJLJ|FJ|F7|.LJ
+....FJL-7.||.||||...
+....L---J.LJ.LJLJ...
+";
+        assert_eq!(solve(larger), (70, 8, 8, 'F'));
+
+        let junk = "\
+FF7FSF7F7F7F7F7F---7
+L|LJ||||||||||||F--J
+FL-7LJLJ||||||LJL-77
+F--JF--7||LJLJ7F7FJ-
+L---JF-JLJ.||-FJLJJ7
+|F|F-JF---7F7-L7L|7|
+|FFJF7L7F-JF7|JL---7
+7-L-JL7||F7|L7F-7F7|
+L.L7LFJ|||||FJL7||LJ
+L7JLJL-JLJLJL--JLJ.L
+";
+        assert_eq!(solve(junk), (80, 10, 10, '7'));
+    }
+
+    #[test]
+    fn render() {
+        let grid = Grid::from_input("..F7.\n.FJ|.\nSJ.L7\n|F--J\nLJ...\n").unwrap();
+        let main_loop = grid.find_loop().unwrap();
+        assert_eq!(
+            grid.render(&main_loop),
+            "..┌┐.\n.┌┘│.\n┌┘I└┐\n│┌──┘\n└┘...\n"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Grid::from_input("...\n.F.\n").is_err());
+        assert!(Grid::from_input("S.S\n").is_err());
+        assert!(Grid::from_input("S.X\n").is_err());
+        assert!(Grid::from_input("S..\n...\n")
+            .unwrap()
+            .find_loop()
+            .is_none());
+    }
+}
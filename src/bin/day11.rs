@@ -0,0 +1,187 @@
+use aoc2023::args::flag_value;
+use aoc2023::error::ParseError;
+use aoc2023::pos::Pos2;
+use std::error::Error;
+use std::{env, fs};
+
+struct Image {
+    galaxies: Vec<Pos2>,
+    height: usize,
+    width: usize,
+}
+
+impl Image {
+    fn from_input(input: &str) -> Result<Image, ParseError> {
+        let width = input.lines().next().map_or(0, |line| line.len());
+        let mut galaxies = vec![];
+        let mut height = 0;
+
+        for (y, line) in input.lines().enumerate() {
+            if line.len() != width {
+                return Err(ParseError(format!(
+                    "line {}: expected {} columns, found {}",
+                    y + 1,
+                    width,
+                    line.len()
+                )));
+            }
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    '#' => galaxies.push(Pos2 {
+                        y: y as i64,
+                        x: x as i64,
+                    }),
+                    '.' => {}
+                    _ => {
+                        return Err(ParseError(format!(
+                            "line {}, column {}: unknown character '{}'",
+                            y + 1,
+                            x + 1,
+                            c
+                        )))
+                    }
+                }
+            }
+            height += 1;
+        }
+
+        Ok(Image {
+            galaxies,
+            height,
+            width,
+        })
+    }
+
+    /// The galaxies after every empty row and column grows to `factor` rows
+    /// or columns, or `None` if a coordinate no longer fits.
+    fn expanded(&self, factor: u64) -> Option<Vec<Pos2>> {
+        // empty_before[i]: how many empty rows (or columns) precede row i.
+        let empty_before = |len: usize, coord: fn(&Pos2) -> i64| {
+            let mut occupied = vec![false; len];
+            for galaxy in &self.galaxies {
+                occupied[coord(galaxy) as usize] = true;
+            }
+            occupied
+                .iter()
+                .scan(0, |empty, occupied| {
+                    let before = *empty;
+                    *empty += !occupied as i128;
+                    Some(before)
+                })
+                .collect::<Vec<_>>()
+        };
+        let rows = empty_before(self.height, |pos| pos.y);
+        let cols = empty_before(self.width, |pos| pos.x);
+
+        let grow = factor as i128 - 1;
+        self.galaxies
+            .iter()
+            .map(|galaxy| {
+                let y = galaxy.y as i128 + rows[galaxy.y as usize] * grow;
+                let x = galaxy.x as i128 + cols[galaxy.x as usize] * grow;
+                Some(Pos2 {
+                    y: y.try_into().ok()?,
+                    x: x.try_into().ok()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The sum of the Manhattan distances between all pairs, in O(n log n).
+/// Distances split by axis, and on a sorted axis the `i`th coordinate is
+/// `c[i] * i - (c[0] + ... + c[i - 1])` away from those before it in total.
+fn sum_of_distances(positions: &[Pos2]) -> u128 {
+    let axis = |coord: fn(&Pos2) -> i64| {
+        let mut coords = positions.iter().map(coord).collect::<Vec<_>>();
+        coords.sort_unstable();
+
+        let mut before = 0i128;
+        let mut sum = 0i128;
+        for (i, c) in coords.iter().enumerate() {
+            sum += *c as i128 * i as i128 - before;
+            before += *c as i128;
+        }
+        sum as u128
+    };
+
+    axis(|pos| pos.y) + axis(|pos| pos.x)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("inputs/day11.txt").expect("Could not read input");
+    let args: Vec<String> = env::args().collect();
+
+    let image = Image::from_input(&contents)?;
+
+    let factors = match flag_value(&args, "--factor")? {
+        Some(factor) => vec![("Expanded by ".to_string() + factor, factor.parse()?)],
+        None => vec![("Part 1".to_string(), 2), ("Part 2".to_string(), 1_000_000)],
+    };
+    for (label, factor) in factors {
+        let galaxies = image
+            .expanded(factor)
+            .ok_or_else(|| format!("expanding by {} overflows", factor))?;
+        println!("{}: {}", label, sum_of_distances(&galaxies));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+...#......
+.......#..
+#.........
+..........
+......#...
+.#........
+.........#
+..........
+.......#..
+#...#.....
+";
+
+    fn naive(positions: &[Pos2]) -> u128 {
+        let mut sum = 0;
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                sum += a.manhattan(b) as u128;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn example() {
+        let image = Image::from_input(EXAMPLE).unwrap();
+        let sum = |factor| sum_of_distances(&image.expanded(factor).unwrap());
+        assert_eq!(sum(2), 374);
+        assert_eq!(sum(10), 1030);
+        assert_eq!(sum(100), 8410);
+
+        let expanded = image.expanded(2).unwrap();
+        assert_eq!(expanded[4], Pos2 { y: 6, x: 1 });
+        assert_eq!(expanded[8], Pos2 { y: 11, x: 5 });
+        assert_eq!(expanded[4].manhattan(&expanded[8]), 9);
+    }
+
+    #[test]
+    fn matches_pairwise_sum() {
+        let image = Image::from_input(EXAMPLE).unwrap();
+        for factor in [0, 1, 2, 7, 1_000_000, u32::MAX as u64] {
+            let galaxies = image.expanded(factor).unwrap();
+            assert_eq!(sum_of_distances(&galaxies), naive(&galaxies));
+        }
+        assert_eq!(image.expanded(u64::MAX), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Image::from_input("..#\n.#\n").is_err());
+        assert!(Image::from_input("..#\n.x.\n").is_err());
+    }
+}
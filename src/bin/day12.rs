@@ -0,0 +1,290 @@
+use aoc2023::args::flag_value;
+use aoc2023::error::ParseError;
+use aoc2023::rng::XorShift;
+use itertools::Itertools;
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::{env, fs};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Spring {
+    Operational,
+    Damaged,
+    Unknown,
+}
+
+impl Spring {
+    fn from_char(c: char) -> Option<Spring> {
+        match c {
+            '.' => Some(Spring::Operational),
+            '#' => Some(Spring::Damaged),
+            '?' => Some(Spring::Unknown),
+            _ => None,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Spring::Operational => '.',
+            Spring::Damaged => '#',
+            Spring::Unknown => '?',
+        }
+    }
+}
+
+/// One row of springs and the sizes of its contiguous damaged groups.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Record {
+    springs: Vec<Spring>,
+    groups: Vec<usize>,
+}
+
+impl FromStr for Record {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (springs, groups) = s
+            .split_once(' ')
+            .ok_or_else(|| ParseError(format!("expected springs and groups: {}", s)))?;
+
+        let springs = springs
+            .chars()
+            .map(|c| {
+                Spring::from_char(c).ok_or_else(|| ParseError(format!("unknown spring '{}'", c)))
+            })
+            .collect::<Result<_, _>>()?;
+        let groups = groups
+            .split(',')
+            .map(|group| match group.parse() {
+                Ok(size) if size > 0 => Ok(size),
+                _ => Err(ParseError(format!("bad group size '{}'", group))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Record { springs, groups })
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let springs = self.springs.iter().map(|s| s.to_char()).collect::<String>();
+        write!(f, "{} {}", springs, self.groups.iter().join(","))
+    }
+}
+
+impl Record {
+    /// `factor` copies of the springs joined by unknowns, and `factor`
+    /// copies of the groups.
+    fn unfold(&self, factor: usize) -> Record {
+        Record {
+            springs: vec![self.springs.clone(); factor].join(&Spring::Unknown),
+            groups: self.groups.repeat(factor),
+        }
+    }
+
+    /// Counts the ways to fill in the unknowns so the damaged springs form
+    /// exactly `groups`, or `None` if there are too many for a `u64`.
+    fn arrangements(&self) -> Option<u64> {
+        let mut memo = vec![vec![None; self.groups.len() + 1]; self.springs.len() + 1];
+        self.count(0, 0, &mut memo)
+    }
+
+    /// Arrangements of `springs[i..]` into `groups[j..]`, where the spring
+    /// before `i` (if any) is operational.
+    fn count(&self, i: usize, j: usize, memo: &mut Vec<Vec<Option<u64>>>) -> Option<u64> {
+        if let Some(ways) = memo[i][j] {
+            return Some(ways);
+        }
+
+        let rest = &self.springs[i..];
+        let ways = match self.groups.get(j) {
+            None => !rest.contains(&Spring::Damaged) as u64,
+            Some(_) if rest.is_empty() => 0,
+            Some(&size) => {
+                let mut ways = 0;
+                if rest[0] != Spring::Damaged {
+                    ways = self.count(i + 1, j, memo)?;
+                }
+                let fits = size <= rest.len()
+                    && !rest[..size].contains(&Spring::Operational)
+                    && rest.get(size) != Some(&Spring::Damaged);
+                if fits {
+                    let placed = self.count((i + size + 1).min(self.springs.len()), j + 1, memo)?;
+                    ways = ways.checked_add(placed)?;
+                }
+                ways
+            }
+        };
+
+        memo[i][j] = Some(ways);
+        Some(ways)
+    }
+
+    /// Tries every way to fill in the unknowns, for rows with at most
+    /// `max_unknowns` of them.
+    fn brute_force(&self, max_unknowns: u32) -> Option<u64> {
+        let unknowns = self
+            .springs
+            .iter()
+            .positions(|spring| *spring == Spring::Unknown)
+            .collect_vec();
+        if unknowns.len() > max_unknowns as usize {
+            return None;
+        }
+
+        let matching = (0..1u64 << unknowns.len())
+            .filter(|bits| {
+                let mut springs = self.springs.clone();
+                for (bit, ix) in unknowns.iter().enumerate() {
+                    springs[*ix] = if bits >> bit & 1 == 1 {
+                        Spring::Damaged
+                    } else {
+                        Spring::Operational
+                    };
+                }
+                let groups = springs
+                    .chunk_by(|a, b| a == b)
+                    .filter(|run| run[0] == Spring::Damaged)
+                    .map(|run| run.len())
+                    .collect_vec();
+                groups == self.groups
+            })
+            .count();
+
+        Some(matching as u64)
+    }
+}
+
+/// Counts random small rows, some unfolded, both ways.
+fn check_brute_force(rows: usize, seed: u64) -> Result<(), String> {
+    let mut rng = XorShift::new(seed);
+
+    for _ in 0..rows {
+        let len = 1 + rng.below(8) as usize;
+        let springs = (0..len)
+            .map(|_| [Spring::Operational, Spring::Damaged, Spring::Unknown][rng.below(3) as usize])
+            .collect();
+        let groups = (0..1 + rng.below(3))
+            .map(|_| 1 + rng.below(3) as usize)
+            .collect();
+        let record = Record { springs, groups }.unfold(1 + rng.below(2) as usize);
+
+        if let Some(expected) = record.brute_force(16) {
+            let counted = record.arrangements();
+            if counted != Some(expected) {
+                return Err(format!(
+                    "{}: {:?} arrangements, but brute force finds {}",
+                    record, counted, expected
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_input(input: &str) -> Result<Vec<Record>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(ix, line)| {
+            line.parse()
+                .map_err(|e: ParseError| ParseError(format!("line {}: {}", ix + 1, e.0)))
+        })
+        .collect()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    if let Some(rows) = flag_value(&args, "--check")? {
+        check_brute_force(rows.parse()?, 0x5eed)?;
+        println!("{} random rows agree with brute force", rows);
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string("inputs/day12.txt").expect("Could not read input");
+    let records = parse_input(&contents)?;
+
+    let total = |factor| {
+        records
+            .iter()
+            .try_fold(0u64, |total, record| {
+                total.checked_add(record.unfold(factor).arrangements()?)
+            })
+            .ok_or_else(|| format!("unfolded {} times, the total overflows", factor))
+    };
+
+    match flag_value(&args, "--unfold")? {
+        Some(factor) => {
+            let factor = factor.parse()?;
+            println!("Unfolded {} times: {}", factor, total(factor)?);
+        }
+        None => {
+            println!("Part 1: {}", total(1)?);
+            println!("Part 2: {}", total(5)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+???.### 1,1,3
+.??..??...?##. 1,1,3
+?#?#?#?#?#?#?#? 1,3,1,6
+????.#...#... 4,1,1
+????.######..#####. 1,6,5
+?###???????? 3,2,1
+";
+
+    #[test]
+    fn example() {
+        let records = parse_input(EXAMPLE).unwrap();
+        let counts = |factor| {
+            records
+                .iter()
+                .map(|record| record.unfold(factor).arrangements().unwrap())
+                .collect_vec()
+        };
+        assert_eq!(counts(1), [1, 4, 1, 1, 4, 10]);
+        assert_eq!(counts(5), [1, 16384, 1, 16, 2500, 506250]);
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| r.brute_force(16).unwrap())
+                .collect_vec(),
+            counts(1)
+        );
+    }
+
+    #[test]
+    fn unfold() {
+        let record: Record = ".# 1".parse().unwrap();
+        assert_eq!(record.unfold(5).to_string(), ".#?.#?.#?.#?.# 1,1,1,1,1");
+        assert_eq!(record.unfold(1), record);
+        assert_eq!(record.unfold(0).arrangements(), Some(1));
+
+        let record: Record = "?###???????? 3,2,1".parse().unwrap();
+        assert_eq!(record.unfold(5).arrangements(), Some(506250));
+        assert_eq!(record.unfold(40).arrangements(), None);
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        check_brute_force(2000, 12).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("???.###".parse::<Record>().is_err());
+        assert!("??x 1".parse::<Record>().is_err());
+        assert!("??? 1,,2".parse::<Record>().is_err());
+        assert!("??? 0".parse::<Record>().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use aoc2023::args::flag_value;
+use aoc2023::error::ParseError;
+use std::error::Error;
+use std::{env, fs};
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Ord, PartialOrd)]
+struct Pos2 {
+    y: i32,
+    x: i32,
+}
+
+#[derive(Debug, Hash, Copy, Clone, PartialEq, Eq)]
+enum Cell {
+    Ash,
+    Rock,
+}
+
+#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+struct Grid {
+    width: i32,
+    height: i32,
+    contents: Vec<Cell>,
+}
+
+/// Where a pattern is mirrored: between two columns or two rows, given by
+/// how many lie left of or above the line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Reflection {
+    Vertical(usize),
+    Horizontal(usize),
+}
+
+impl Reflection {
+    fn summary(self) -> usize {
+        match self {
+            Reflection::Vertical(columns) => columns,
+            Reflection::Horizontal(rows) => 100 * rows,
+        }
+    }
+}
+
+impl Grid {
+    /// Rows and columns become bits of a `u64`, so neither may be longer
+    /// than 64.
+    fn from_input(input: &str) -> Result<Grid, ParseError> {
+        let lines: Vec<_> = input.lines().collect();
+        let width = lines.first().map_or(0, |line| line.len());
+        if width == 0 || width > 64 || lines.len() > 64 {
+            return Err(ParseError(format!(
+                "pattern must be 1 to 64 cells each way, found {}x{}",
+                width,
+                lines.len()
+            )));
+        }
+
+        let mut contents = vec![];
+        for (y, line) in lines.iter().enumerate() {
+            if line.len() != width {
+                return Err(ParseError(format!(
+                    "line {}: expected {} columns, found {}",
+                    y + 1,
+                    width,
+                    line.len()
+                )));
+            }
+            for (x, c) in line.chars().enumerate() {
+                contents.push(match c {
+                    '.' => Cell::Ash,
+                    '#' => Cell::Rock,
+                    _ => {
+                        return Err(ParseError(format!(
+                            "line {}, column {}: unknown cell '{}'",
+                            y + 1,
+                            x + 1,
+                            c
+                        )))
+                    }
+                });
+            }
+        }
+
+        Ok(Grid {
+            width: width as i32,
+            height: lines.len() as i32,
+            contents,
+        })
+    }
+
+    fn get(&self, pos: &Pos2) -> Cell {
+        debug_assert!(
+            pos.x >= 0 && pos.x < self.width,
+            "x={} out of bounds 0..{}",
+            pos.x,
+            self.width
+        );
+        debug_assert!(
+            pos.y >= 0 && pos.y < self.height,
+            "y={} out of bounds 0..{}",
+            pos.y,
+            self.height
+        );
+
+        let ix = pos.y * self.width + pos.x;
+        self.contents[ix as usize]
+    }
+
+    fn set(&mut self, pos: &Pos2, cell: Cell) {
+        let ix = pos.y * self.width + pos.x;
+        self.contents[ix as usize] = cell;
+    }
+
+    fn rotate_right(&self) -> Grid {
+        let mut grid = self.clone();
+        (grid.height, grid.width) = (grid.width, grid.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let old_pos = Pos2 { y, x };
+                let new_pos = Pos2 {
+                    y: x,
+                    x: self.height - y - 1,
+                };
+                grid.set(&new_pos, self.get(&old_pos));
+            }
+        }
+
+        grid
+    }
+
+    /// Each row as a bitmask, with bit `x` set for rock in column `x`.
+    fn row_masks(&self) -> Vec<u64> {
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .filter(|x| self.get(&Pos2 { y, x: *x }) == Cell::Rock)
+                    .fold(0, |mask, x| mask | 1 << x)
+            })
+            .collect()
+    }
+
+    /// Finds the mirror line with exactly `smudges` cells that differ from
+    /// their reflection. Columns of this grid are the rows of the grid
+    /// rotated right, in the same order.
+    fn reflection(&self, smudges: u32) -> Option<Reflection> {
+        horizontal_mirror(&self.row_masks(), smudges)
+            .map(Reflection::Horizontal)
+            .or_else(|| {
+                horizontal_mirror(&self.rotate_right().row_masks(), smudges)
+                    .map(Reflection::Vertical)
+            })
+    }
+}
+
+/// The number of rows above the first line between rows where the rows
+/// differ from their reflection in exactly `smudges` bits in total.
+fn horizontal_mirror(rows: &[u64], smudges: u32) -> Option<usize> {
+    (1..rows.len()).find(|line| {
+        let above = rows[..*line].iter().rev();
+        let below = rows[*line..].iter();
+        above
+            .zip(below)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>()
+            == smudges
+    })
+}
+
+fn parse_input(input: &str) -> Result<Vec<Grid>, ParseError> {
+    input
+        .split("\n\n")
+        .filter(|pattern| !pattern.trim().is_empty())
+        .enumerate()
+        .map(|(ix, pattern)| {
+            Grid::from_input(pattern)
+                .map_err(|e| ParseError(format!("pattern {}: {}", ix + 1, e.0)))
+        })
+        .collect()
+}
+
+fn summarize(patterns: &[Grid], smudges: u32) -> Result<usize, String> {
+    patterns
+        .iter()
+        .enumerate()
+        .map(|(ix, pattern)| {
+            pattern
+                .reflection(smudges)
+                .map(Reflection::summary)
+                .ok_or_else(|| {
+                    format!("pattern {}: no reflection with {} smudges", ix + 1, smudges)
+                })
+        })
+        .sum()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("inputs/day13.txt").expect("Could not read input");
+    let args: Vec<String> = env::args().collect();
+
+    let patterns = parse_input(&contents)?;
+
+    match flag_value(&args, "--smudges")? {
+        Some(smudges) => {
+            let smudges = smudges.parse()?;
+            println!(
+                "With {} smudges: {}",
+                smudges,
+                summarize(&patterns, smudges)?
+            );
+        }
+        None => {
+            println!("Part 1: {}", summarize(&patterns, 0)?);
+            println!("Part 2: {}", summarize(&patterns, 1)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+#.##..##.
+..#.##.#.
+##......#
+##......#
+..#.##.#.
+..##..##.
+#.#.##.#.
+
+#...##..#
+#....#..#
+..##..###
+#####.##.
+#####.##.
+..##..###
+#....#..#
+";
+
+    #[test]
+    fn example() {
+        let patterns = parse_input(EXAMPLE).unwrap();
+        assert_eq!(patterns[0].reflection(0), Some(Reflection::Vertical(5)));
+        assert_eq!(patterns[1].reflection(0), Some(Reflection::Horizontal(4)));
+        assert_eq!(summarize(&patterns, 0), Ok(405));
+
+        assert_eq!(patterns[0].reflection(1), Some(Reflection::Horizontal(3)));
+        assert_eq!(patterns[1].reflection(1), Some(Reflection::Horizontal(1)));
+        assert_eq!(summarize(&patterns, 1), Ok(400));
+    }
+
+    #[test]
+    fn rotation() {
+        let grid = Grid::from_input("#..\n.#.\n").unwrap();
+        let rotated = grid.rotate_right();
+        assert_eq!(rotated, Grid::from_input(".#\n#.\n..\n").unwrap());
+        assert_eq!(grid.row_masks(), [0b001, 0b010]);
+        assert_eq!(rotated.row_masks(), [0b10, 0b01, 0b00]);
+        assert_eq!(rotated.rotate_right().rotate_right().rotate_right(), grid);
+    }
+
+    #[test]
+    fn smudges() {
+        // Lines after rows 1, 2 and 3 have 4, 5 and 1 cells off.
+        let rows = [0b1100, 0b0011, 0b0111, 0b0011];
+        assert_eq!(horizontal_mirror(&rows, 0), None);
+        assert_eq!(horizontal_mirror(&rows, 1), Some(3));
+        assert_eq!(horizontal_mirror(&rows, 4), Some(1));
+        assert_eq!(horizontal_mirror(&rows, 5), Some(2));
+        assert_eq!(horizontal_mirror(&[0b1], 0), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Grid::from_input("#.\n#\n").is_err());
+        assert!(Grid::from_input("#x\n").is_err());
+        assert!(Grid::from_input(&".".repeat(65)).is_err());
+        assert!(Grid::from_input("").is_err());
+    }
+}
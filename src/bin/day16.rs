@@ -1,8 +1,17 @@
-use std::{collections::HashSet, fs};
+use aoc2023::args::{flag_value, flag_values};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    fs, io,
+    str::FromStr,
+    thread,
+    time::Instant,
+};
 
 use itertools::Itertools;
 
-#[derive(Hash, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
 struct Pos2 {
     y: i32,
     x: i32,
@@ -19,34 +28,9 @@ impl Pos2 {
             dir,
         )
     }
-
-    fn step(&self, dir: Dir, u8: u8) -> ((Pos2, Dir), Option<(Pos2, Dir)>) {
-        match (u8, dir) {
-            (b'.', dir) => (self.forward(dir), None),
-            (b'|', dir) if dir == Dir::Left || dir == Dir::Right => {
-                (self.forward(Dir::Up), Some(self.forward(Dir::Down)))
-            }
-            (b'|', dir) => (self.forward(dir), None),
-            (b'-', dir) if dir == Dir::Up || dir == Dir::Down => {
-                (self.forward(Dir::Left), Some(self.forward(Dir::Right)))
-            }
-            (b'-', dir) => (self.forward(dir), None),
-            (b'\\', Dir::Right) => (self.forward(Dir::Down), None),
-            (b'\\', Dir::Down) => (self.forward(Dir::Right), None),
-            (b'\\', Dir::Up) => (self.forward(Dir::Left), None),
-            (b'\\', Dir::Left) => (self.forward(Dir::Up), None),
-
-            (b'/', Dir::Right) => (self.forward(Dir::Up), None),
-            (b'/', Dir::Down) => (self.forward(Dir::Left), None),
-            (b'/', Dir::Up) => (self.forward(Dir::Right), None),
-            (b'/', Dir::Left) => (self.forward(Dir::Down), None),
-
-            _ => panic!("{}", u8),
-        }
-    }
 }
 
-#[derive(Hash, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
 enum Dir {
     Up,
     Right,
@@ -55,6 +39,12 @@ enum Dir {
 }
 
 impl Dir {
+    const ALL: [Dir; 4] = [Dir::Up, Dir::Right, Dir::Down, Dir::Left];
+
+    fn opposite(self) -> Dir {
+        Dir::ALL[(self as usize + 2) % 4]
+    }
+
     fn to_diff(self) -> Pos2 {
         match self {
             Dir::Up => Pos2 { y: -1, x: 0 },
@@ -65,6 +55,153 @@ impl Dir {
     }
 }
 
+/// A set of directions, using the same bit layout as `TileMask`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+struct DirSet(u8);
+
+impl DirSet {
+    const NONE: DirSet = DirSet(0);
+
+    fn of(dirs: &[Dir]) -> DirSet {
+        DirSet(dirs.iter().fold(0, |bits, dir| bits | 1 << *dir as u8))
+    }
+
+    fn iter(self) -> impl Iterator<Item = Dir> {
+        Dir::ALL
+            .into_iter()
+            .filter(move |dir| self.0 & 1 << *dir as u8 != 0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Optic {
+    Empty,
+    /// `|`
+    SplitVertical,
+    /// `-`
+    SplitHorizontal,
+    /// `\`
+    MirrorBackslash,
+    /// `/`
+    MirrorSlash,
+    Absorber,
+    /// Lets beams travelling in the given direction through and absorbs the
+    /// rest.
+    Diode(Dir),
+    /// Splits a beam into every direction except back where it came from.
+    Tee,
+}
+
+impl Optic {
+    fn outgoing(self, dir: Dir) -> DirSet {
+        match (self, dir) {
+            (Optic::Empty, dir) => DirSet::of(&[dir]),
+            (Optic::SplitVertical, Dir::Left | Dir::Right) => DirSet::of(&[Dir::Up, Dir::Down]),
+            (Optic::SplitHorizontal, Dir::Up | Dir::Down) => DirSet::of(&[Dir::Left, Dir::Right]),
+            (Optic::SplitVertical | Optic::SplitHorizontal, dir) => DirSet::of(&[dir]),
+            (Optic::MirrorBackslash, Dir::Right) => DirSet::of(&[Dir::Down]),
+            (Optic::MirrorBackslash, Dir::Down) => DirSet::of(&[Dir::Right]),
+            (Optic::MirrorBackslash, Dir::Up) => DirSet::of(&[Dir::Left]),
+            (Optic::MirrorBackslash, Dir::Left) => DirSet::of(&[Dir::Up]),
+            (Optic::MirrorSlash, Dir::Right) => DirSet::of(&[Dir::Up]),
+            (Optic::MirrorSlash, Dir::Down) => DirSet::of(&[Dir::Left]),
+            (Optic::MirrorSlash, Dir::Up) => DirSet::of(&[Dir::Right]),
+            (Optic::MirrorSlash, Dir::Left) => DirSet::of(&[Dir::Down]),
+            (Optic::Absorber, _) => DirSet::NONE,
+            (Optic::Diode(allowed), dir) if allowed == dir => DirSet::of(&[dir]),
+            (Optic::Diode(_), _) => DirSet::NONE,
+            (Optic::Tee, dir) => DirSet(0b1111 & !(1 << dir.opposite() as u8)),
+        }
+    }
+}
+
+impl FromStr for Optic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "empty" => Optic::Empty,
+            "split-vertical" => Optic::SplitVertical,
+            "split-horizontal" => Optic::SplitHorizontal,
+            "mirror-backslash" => Optic::MirrorBackslash,
+            "mirror-slash" => Optic::MirrorSlash,
+            "absorber" => Optic::Absorber,
+            "diode-up" => Optic::Diode(Dir::Up),
+            "diode-right" => Optic::Diode(Dir::Right),
+            "diode-down" => Optic::Diode(Dir::Down),
+            "diode-left" => Optic::Diode(Dir::Left),
+            "tee" => Optic::Tee,
+            _ => return Err(format!("unknown optic: {}", s)),
+        })
+    }
+}
+
+/// Maps each grid byte to the optic found on that tile.
+struct Optics {
+    table: [Option<Optic>; 256],
+}
+
+impl Optics {
+    /// The five puzzle tiles, plus `#` absorbers, `^>v<` diodes and `+` tees.
+    fn standard() -> Optics {
+        let mut optics = Optics { table: [None; 256] };
+        for (c, optic) in [
+            (b'.', Optic::Empty),
+            (b'|', Optic::SplitVertical),
+            (b'-', Optic::SplitHorizontal),
+            (b'\\', Optic::MirrorBackslash),
+            (b'/', Optic::MirrorSlash),
+            (b'#', Optic::Absorber),
+            (b'^', Optic::Diode(Dir::Up)),
+            (b'>', Optic::Diode(Dir::Right)),
+            (b'v', Optic::Diode(Dir::Down)),
+            (b'<', Optic::Diode(Dir::Left)),
+            (b'+', Optic::Tee),
+        ] {
+            optics.set(c, optic);
+        }
+        optics
+    }
+
+    fn set(&mut self, c: u8, optic: Optic) {
+        self.table[c as usize] = Some(optic);
+    }
+
+    /// Callers must have checked the grid with `check` first.
+    fn get(&self, c: u8) -> Optic {
+        self.table[c as usize].unwrap_or_else(|| panic!("no optic for {:?}", c as char))
+    }
+
+    /// Reports the first tile of `grid` that has no optic, so that `get`
+    /// can't panic halfway through a simulation.
+    fn check(&self, grid: &Grid<u8>) -> Result<(), String> {
+        match grid
+            .contents
+            .iter()
+            .position(|&c| self.table[c as usize].is_none())
+        {
+            Some(ix) => Err(format!(
+                "line {}, column {}: no optic for {:?}",
+                ix as i32 / grid.width + 1,
+                ix as i32 % grid.width + 1,
+                grid.contents[ix] as char
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Parses an override such as `X=absorber`.
+    fn set_from_arg(&mut self, arg: &str) -> Result<(), String> {
+        match arg.split_once('=') {
+            Some((c, optic)) if c.len() == 1 => {
+                self.set(c.as_bytes()[0], optic.parse()?);
+                Ok(())
+            }
+            _ => Err(format!("expected <char>=<optic>, got: {}", arg)),
+        }
+    }
+}
+
 struct Grid<T> {
     height: i32,
     width: i32,
@@ -90,18 +227,69 @@ impl<T> Grid<T> {
     }
 }
 
-struct Beam<'a> {
+trait Visited {
+    fn new(grid: &Grid<u8>) -> Self;
+    /// Returns `false` if the beam has already passed `pos` going `dir`.
+    fn insert(&mut self, pos: Pos2, dir: Dir) -> bool;
+    fn energized_tile_count(&self) -> i32;
+}
+
+/// One byte per tile, with a bit set for each direction a beam has
+/// travelled through it.
+struct TileMask {
+    width: i32,
+    tiles: Vec<u8>,
+}
+
+impl Visited for TileMask {
+    fn new(grid: &Grid<u8>) -> Self {
+        TileMask {
+            width: grid.width,
+            tiles: vec![0; grid.contents.len()],
+        }
+    }
+
+    fn insert(&mut self, pos: Pos2, dir: Dir) -> bool {
+        let tile = &mut self.tiles[(pos.y * self.width + pos.x) as usize];
+        let bit = 1 << dir as u8;
+        let new = *tile & bit == 0;
+        *tile |= bit;
+        new
+    }
+
+    fn energized_tile_count(&self) -> i32 {
+        self.tiles.iter().filter(|&&t| t != 0).count() as i32
+    }
+}
+
+impl Visited for HashSet<(Pos2, Dir)> {
+    fn new(_grid: &Grid<u8>) -> Self {
+        HashSet::new()
+    }
+
+    fn insert(&mut self, pos: Pos2, dir: Dir) -> bool {
+        HashSet::insert(self, (pos, dir))
+    }
+
+    fn energized_tile_count(&self) -> i32 {
+        self.iter().map(|(p, _)| *p).unique().count() as i32
+    }
+}
+
+struct Beam<'a, V: Visited = TileMask> {
     grid: &'a Grid<u8>,
+    optics: &'a Optics,
     beams: Vec<(Pos2, Dir)>,
-    visited: HashSet<(Pos2, Dir)>,
+    visited: V,
 }
 
-impl<'a> Beam<'a> {
-    fn new(grid: &'a Grid<u8>) -> Beam<'a> {
+impl<'a, V: Visited> Beam<'a, V> {
+    fn new(grid: &'a Grid<u8>, optics: &'a Optics) -> Beam<'a, V> {
         Beam {
             grid,
+            optics,
             beams: vec![],
-            visited: HashSet::new(),
+            visited: V::new(grid),
         }
     }
 
@@ -113,49 +301,558 @@ impl<'a> Beam<'a> {
                 || pos.x >= self.grid.width
                 || pos.y < 0
                 || pos.y >= self.grid.height
-                || self.visited.contains(&(pos, dir))
+                || !self.visited.insert(pos, dir)
             {
                 continue;
             }
-            self.visited.insert((pos, dir));
-            let c = self.grid.get(&pos);
-            let (next, opt_next) = pos.step(dir, *c);
-            self.beams.push(next);
-            opt_next.into_iter().for_each(|x| self.beams.push(x));
+            let optic = self.optics.get(*self.grid.get(&pos));
+            for out in optic.outgoing(dir).iter() {
+                self.beams.push(pos.forward(out));
+            }
         }
     }
 
     fn energized_tile_count(&self) -> i32 {
-        self.visited.iter().map(|(p, _)| *p).unique().count() as i32
+        self.visited.energized_tile_count()
+    }
+}
+
+fn edge_entries(grid: &Grid<u8>) -> Vec<(Pos2, Dir)> {
+    let mut entries = vec![];
+    for (col, dir) in [(0, Dir::Right), (grid.width - 1, Dir::Left)] {
+        for row in 0..grid.height {
+            entries.push((Pos2 { x: col, y: row }, dir));
+        }
+    }
+    for (row, dir) in [(0, Dir::Down), (grid.height - 1, Dir::Up)] {
+        for col in 0..grid.width {
+            entries.push((Pos2 { x: col, y: row }, dir));
+        }
+    }
+    entries
+}
+
+fn energize<V: Visited>(grid: &Grid<u8>, optics: &Optics, pos: Pos2, dir: Dir) -> i32 {
+    let mut beam = Beam::<V>::new(grid, optics);
+    beam.run(pos, dir);
+    beam.energized_tile_count()
+}
+
+/// Simulates every entry across `threads` workers. Ties are broken by the
+/// position in `entries`, so the result does not depend on the thread count.
+fn best_entry(
+    grid: &Grid<u8>,
+    optics: &Optics,
+    entries: &[(Pos2, Dir)],
+    threads: usize,
+) -> ((Pos2, Dir), i32) {
+    let chunk_size = entries.len().div_ceil(threads.max(1)).max(1);
+
+    let counts: Vec<i32> = thread::scope(|s| {
+        let workers: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&(pos, dir)| energize::<TileMask>(grid, optics, pos, dir))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("worker panicked"))
+            .collect()
+    });
+
+    pick_best(entries, &counts)
+}
+
+/// Picks the highest count, preferring the earliest entry on ties.
+fn pick_best(entries: &[(Pos2, Dir)], counts: &[i32]) -> ((Pos2, Dir), i32) {
+    let (ix, count) = counts
+        .iter()
+        .enumerate()
+        .fold((0, i32::MIN), |best, (ix, &count)| {
+            if count > best.1 {
+                (ix, count)
+            } else {
+                best
+            }
+        });
+
+    (entries[ix], count)
+}
+
+/// The contraption cut into segments: runs of beam that start at an edge
+/// entry or a splitter output, and end where the beam splits again, leaves
+/// the grid or is absorbed.
+struct SegmentGraph {
+    starts: HashMap<(Pos2, Dir), usize>,
+    tiles: Vec<Vec<usize>>,
+    next: Vec<Vec<usize>>,
+}
+
+impl SegmentGraph {
+    fn build(grid: &Grid<u8>, optics: &Optics, entries: &[(Pos2, Dir)]) -> SegmentGraph {
+        let mut graph = SegmentGraph {
+            starts: HashMap::new(),
+            tiles: vec![],
+            next: vec![],
+        };
+        let mut todo = vec![];
+        for &entry in entries {
+            graph.segment_id(entry, &mut todo);
+        }
+
+        while let Some(start) = todo.pop() {
+            let id = graph.starts[&start];
+            let (tiles, outs) = trace_segment(grid, optics, start);
+            graph.tiles[id] = tiles;
+            graph.next[id] = outs
+                .into_iter()
+                .map(|out| graph.segment_id(out, &mut todo))
+                .collect();
+        }
+
+        graph
+    }
+
+    fn segment_id(&mut self, start: (Pos2, Dir), todo: &mut Vec<(Pos2, Dir)>) -> usize {
+        *self.starts.entry(start).or_insert_with(|| {
+            todo.push(start);
+            self.tiles.push(vec![]);
+            self.next.push(vec![]);
+            self.tiles.len() - 1
+        })
+    }
+}
+
+/// Follows a beam until it splits, returning the tiles it covered and the
+/// beams it split into.
+fn trace_segment(
+    grid: &Grid<u8>,
+    optics: &Optics,
+    (mut pos, mut dir): (Pos2, Dir),
+) -> (Vec<usize>, Vec<(Pos2, Dir)>) {
+    let mut tiles = vec![];
+    let mut seen = HashSet::new();
+
+    while pos.x >= 0
+        && pos.x < grid.width
+        && pos.y >= 0
+        && pos.y < grid.height
+        && seen.insert((pos, dir))
+    {
+        tiles.push((pos.y * grid.width + pos.x) as usize);
+        let outs = optics.get(*grid.get(&pos)).outgoing(dir);
+        match outs.iter().collect::<Vec<_>>()[..] {
+            [] => break,
+            [out] => (pos, dir) = pos.forward(out),
+            ref outs => return (tiles, outs.iter().map(|&out| pos.forward(out)).collect()),
+        }
+    }
+
+    (tiles, vec![])
+}
+
+/// Energized tiles for every strongly connected component of a
+/// `SegmentGraph`. Segments in a component all reach each other, so they
+/// share one set: the union of their own tiles and those of every component
+/// downstream.
+struct EnergyTable {
+    component: Vec<usize>,
+    energized: Vec<Vec<u64>>,
+}
+
+impl EnergyTable {
+    fn new(graph: &SegmentGraph, tile_count: usize) -> EnergyTable {
+        let segments = graph.tiles.len();
+        let mut tarjan = Tarjan {
+            next: &graph.next,
+            index: vec![None; segments],
+            low: vec![0; segments],
+            on_stack: vec![false; segments],
+            stack: vec![],
+            counter: 0,
+            components: vec![],
+        };
+        for segment in 0..segments {
+            if tarjan.index[segment].is_none() {
+                tarjan.connect(segment);
+            }
+        }
+
+        let mut component = vec![0; segments];
+        for (id, members) in tarjan.components.iter().enumerate() {
+            for &segment in members {
+                component[segment] = id;
+            }
+        }
+
+        // Tarjan emits components downstream first, so every successor set
+        // is complete by the time it is needed.
+        let mut energized: Vec<Vec<u64>> = vec![];
+        for (id, members) in tarjan.components.iter().enumerate() {
+            let mut bits = vec![0; tile_count.div_ceil(64)];
+            for &segment in members {
+                for &tile in &graph.tiles[segment] {
+                    bits[tile / 64] |= 1 << (tile % 64);
+                }
+                for &next in &graph.next[segment] {
+                    if component[next] != id {
+                        for (b, n) in bits.iter_mut().zip(&energized[component[next]]) {
+                            *b |= n;
+                        }
+                    }
+                }
+            }
+            energized.push(bits);
+        }
+
+        EnergyTable {
+            component,
+            energized,
+        }
+    }
+
+    fn energized_tile_count(&self, graph: &SegmentGraph, start: (Pos2, Dir)) -> i32 {
+        let component = self.component[graph.starts[&start]];
+        self.energized[component]
+            .iter()
+            .map(|bits| bits.count_ones())
+            .sum::<u32>() as i32
+    }
+}
+
+struct Tarjan<'a> {
+    next: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    counter: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn connect(&mut self, v: usize) {
+        self.index[v] = Some(self.counter);
+        self.low[v] = self.counter;
+        self.counter += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+
+        for &w in self.next[v].iter() {
+            match self.index[w] {
+                None => {
+                    self.connect(w);
+                    self.low[v] = self.low[v].min(self.low[w]);
+                }
+                Some(ix) if self.on_stack[w] => self.low[v] = self.low[v].min(ix),
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low[v]) == self.index[v] {
+            let mut members = vec![];
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                members.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.components.push(members);
+        }
+    }
+}
+
+/// Answers every entry from the segment graph instead of simulating it.
+fn best_entry_by_graph(
+    grid: &Grid<u8>,
+    optics: &Optics,
+    entries: &[(Pos2, Dir)],
+) -> ((Pos2, Dir), i32) {
+    let graph = SegmentGraph::build(grid, optics, entries);
+    let table = EnergyTable::new(&graph, grid.contents.len());
+    let counts: Vec<i32> = entries
+        .iter()
+        .map(|&entry| table.energized_tile_count(&graph, entry))
+        .collect();
+    pick_best(entries, &counts)
+}
+
+fn render_energized(grid: &Grid<u8>, mask: &TileMask) -> String {
+    let mut out = String::new();
+    for (ix, tile) in mask.tiles.iter().enumerate() {
+        out.push(if *tile != 0 { '#' } else { '.' });
+        if (ix as i32 + 1) % grid.width == 0 {
+            out.push('\n');
+        }
     }
+    out
 }
 
-fn main() {
+/// Draws the beams like the puzzle does: mirrors and splitters are kept,
+/// empty tiles show the beam direction, or the number of beams if several
+/// cross it.
+fn render_paths(grid: &Grid<u8>, mask: &TileMask) -> String {
+    let mut out = String::new();
+    for (ix, (&c, &tile)) in grid.contents.iter().zip(&mask.tiles).enumerate() {
+        out.push(match (c, tile.count_ones()) {
+            (b'.', 0) => '.',
+            (b'.', 1) => match tile.trailing_zeros() {
+                0 => '^',
+                1 => '>',
+                2 => 'v',
+                _ => '<',
+            },
+            (b'.', n) => char::from_digit(n, 10).unwrap(),
+            (c, _) => c as char,
+        });
+        if (ix as i32 + 1) % grid.width == 0 {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Counts, for every tile, how many of `entries` energize it.
+fn heatmap(grid: &Grid<u8>, optics: &Optics, entries: &[(Pos2, Dir)]) -> Vec<u32> {
+    let mut heat = vec![0; grid.contents.len()];
+    for &(pos, dir) in entries {
+        let mut beam = Beam::<TileMask>::new(grid, optics);
+        beam.run(pos, dir);
+        for (h, tile) in heat.iter_mut().zip(&beam.visited.tiles) {
+            if *tile != 0 {
+                *h += 1;
+            }
+        }
+    }
+    heat
+}
+
+/// Binary PPM, one pixel per tile, going black -> red -> yellow -> white.
+fn write_ppm(path: &str, grid: &Grid<u8>, heat: &[u32]) -> io::Result<()> {
+    let max = heat.iter().copied().max().unwrap_or(0).max(1);
+    let mut out = format!("P6\n{} {}\n255\n", grid.width, grid.height).into_bytes();
+    for &h in heat {
+        let t = h * 765 / max;
+        out.push(t.min(255) as u8);
+        out.push(t.saturating_sub(255).min(255) as u8);
+        out.push(t.saturating_sub(510).min(255) as u8);
+    }
+    fs::write(path, out)
+}
+
+/// Times a sequential scan of every edge entry with the given visited set.
+fn bench<V: Visited>(
+    name: &str,
+    grid: &Grid<u8>,
+    optics: &Optics,
+    entries: &[(Pos2, Dir)],
+    rounds: u32,
+) {
+    let start = Instant::now();
+    let mut max_energy = 0;
+    for _ in 0..rounds {
+        for &(pos, dir) in entries {
+            max_energy = i32::max(max_energy, energize::<V>(grid, optics, pos, dir));
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:>8}: {:?} per scan ({} rounds, max {})",
+        name,
+        elapsed / rounds,
+        rounds,
+        max_energy
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     let contents = fs::read_to_string("inputs/day16.txt").expect("Could not read input");
 
     let grid = Grid::from_input(&contents);
 
-    let mut beam = Beam::new(&grid);
+    let args: Vec<String> = env::args().collect();
+
+    let mut optics = Optics::standard();
+    for value in flag_values(&args, "--optic")? {
+        optics.set_from_arg(value)?;
+    }
+    optics.check(&grid)?;
+
+    if args.iter().any(|arg| arg == "--bench") {
+        let entries = edge_entries(&grid);
+        bench::<HashSet<(Pos2, Dir)>>("HashSet", &grid, &optics, &entries, 5);
+        bench::<TileMask>("TileMask", &grid, &optics, &entries, 5);
+        return Ok(());
+    }
 
+    let mut beam = Beam::<TileMask>::new(&grid, &optics);
     beam.run(Pos2 { x: 0, y: 0 }, Dir::Right);
 
+    match flag_value(&args, "--render")? {
+        Some("energized") => print!("{}", render_energized(&grid, &beam.visited)),
+        Some("paths") => print!("{}", render_paths(&grid, &beam.visited)),
+        Some(mode) => return Err(format!("unknown render mode: {}", mode).into()),
+        None => {}
+    }
+
     println!("Part 1: {}", beam.energized_tile_count());
 
-    let mut max_energy = 0;
-    for (col, dir) in [(0, Dir::Right), (grid.width - 1, Dir::Left)] {
-        for row in 0..grid.height {
-            let mut beam = Beam::new(&grid);
-            beam.run(Pos2 { x: col, y: row }, dir);
-            max_energy = i32::max(max_energy, beam.energized_tile_count());
-        }
+    let entries = edge_entries(&grid);
+    let ((pos, dir), max_energy) = if args.iter().any(|arg| arg == "--simulate") {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        best_entry(&grid, &optics, &entries, threads)
+    } else {
+        best_entry_by_graph(&grid, &optics, &entries)
+    };
+
+    println!(
+        "Part 2: {} (entering at x={} y={} going {:?})",
+        max_energy, pos.x, pos.y, dir
+    );
+
+    if let Some(path) = flag_value(&args, "--heatmap")? {
+        write_ppm(path, &grid, &heatmap(&grid, &optics, &entries))?;
     }
-    for (row, dir) in [(0, Dir::Down), (grid.height - 1, Dir::Up)] {
-        for col in 0..grid.width {
-            let mut beam = Beam::new(&grid);
-            beam.run(Pos2 { x: col, y: row }, dir);
-            max_energy = i32::max(max_energy, beam.energized_tile_count());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r".|...\....
+|.-.\.....
+.....|-...
+........|.
+..........
+.........\
+..../.\\..
+.-.-/..|..
+.|....-|.\
+..//.|....
+";
+
+    #[test]
+    fn sample() {
+        let grid = Grid::from_input(SAMPLE);
+        let start = Pos2 { x: 0, y: 0 };
+        assert_eq!(
+            energize::<TileMask>(&grid, &Optics::standard(), start, Dir::Right),
+            46
+        );
+        assert_eq!(
+            energize::<HashSet<_>>(&grid, &Optics::standard(), start, Dir::Right),
+            46
+        );
+    }
+
+    #[test]
+    fn render_sample() {
+        let grid = Grid::from_input(SAMPLE);
+        let optics = Optics::standard();
+        let mut beam = Beam::<TileMask>::new(&grid, &optics);
+        beam.run(Pos2 { x: 0, y: 0 }, Dir::Right);
+
+        let energized = "\
+######....
+.#...#....
+.#...#####
+.#...##...
+.#...##...
+.#...##...
+.#..####..
+########..
+.#######..
+.#...#.#..
+";
+        assert_eq!(render_energized(&grid, &beam.visited), energized);
+
+        let paths = r">|<<<\....
+|v-.\^....
+.v...|->>>
+.v...v^.|.
+.v...v^...
+.v...v^..\
+.v../2\\..
+<->-/vv|..
+.|<<<2-|.\
+.v//.|.v..
+";
+        assert_eq!(render_paths(&grid, &beam.visited), paths);
+    }
+
+    #[test]
+    fn best_entry_is_independent_of_thread_count() {
+        let grid = Grid::from_input(SAMPLE);
+        let entries = edge_entries(&grid);
+        let expected = (Pos2 { x: 3, y: 0 }, Dir::Down);
+        for threads in [1, 2, 3, 7, 64] {
+            assert_eq!(
+                best_entry(&grid, &Optics::standard(), &entries, threads),
+                (expected, 51)
+            );
         }
     }
 
-    println!("Part 2: {}", max_energy);
+    #[test]
+    fn variant_optics() {
+        let grid = Grid::from_input("..#..\n.....\n..+.<\n.....\n..v..\n");
+        let optics = Optics::standard();
+        let run = |pos, dir| energize::<TileMask>(&grid, &optics, pos, dir);
+
+        // The `v` diode absorbs beams going the wrong way.
+        assert_eq!(run(Pos2 { x: 2, y: 4 }, Dir::Up), 1);
+        assert_eq!(run(Pos2 { x: 4, y: 0 }, Dir::Down), 3);
+        // The tee fans out up, right and down: the `#` stops the upward
+        // beam, `<` the rightward one, and `v` lets the downward one out.
+        assert_eq!(run(Pos2 { x: 0, y: 2 }, Dir::Right), 9);
+        assert_eq!(run(Pos2 { x: 2, y: 0 }, Dir::Down), 1);
+
+        let mut custom = Optics::standard();
+        custom.set_from_arg("#=empty").unwrap();
+        assert_eq!(
+            energize::<TileMask>(&grid, &custom, Pos2 { x: 2, y: 0 }, Dir::Down),
+            9
+        );
+        assert!(custom.set_from_arg("#=laser").is_err());
+
+        let unknown = Grid::from_input("..#\n.X.\n");
+        assert_eq!(
+            Optics::standard().check(&unknown),
+            Err("line 2, column 2: no optic for 'X'".to_string())
+        );
+        custom.set_from_arg("X=absorber").unwrap();
+        assert_eq!(custom.check(&unknown), Ok(()));
+    }
+
+    #[test]
+    fn segment_graph_matches_simulation() {
+        let variant = "..#..\n.....\n..+.<\n.....\n..v..\n";
+        let looping = "/.-.\\\n.....\n|...|\n.....\n\\.-./\n";
+        for input in [SAMPLE, variant, looping] {
+            let grid = Grid::from_input(input);
+            let optics = Optics::standard();
+            let entries = edge_entries(&grid);
+            let graph = SegmentGraph::build(&grid, &optics, &entries);
+            let table = EnergyTable::new(&graph, grid.contents.len());
+            for &(pos, dir) in &entries {
+                assert_eq!(
+                    table.energized_tile_count(&graph, (pos, dir)),
+                    energize::<TileMask>(&grid, &optics, pos, dir),
+                    "entering at {:?} going {:?}",
+                    pos,
+                    dir
+                );
+            }
+        }
+    }
 }
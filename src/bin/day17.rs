@@ -1,8 +1,17 @@
+use aoc2023::args::{flag_value, flag_values};
+use aoc2023::error::ParseError;
+use aoc2023::pos;
+use aoc2023::rng::XorShift;
 use priority_queue::PriorityQueue;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::env;
+use std::error::Error;
 use std::fs;
 use std::hash::Hash;
 
+use itertools::Itertools;
+
 #[derive(Debug, Hash, PartialEq, Eq, Copy, Clone, PartialOrd, Ord)]
 struct Pos2 {
     y: i32,
@@ -19,7 +28,18 @@ impl Pos2 {
     }
 
     fn manhattan(&self, other: &Self) -> i32 {
-        (self.x.abs_diff(other.x) + self.y.abs_diff(other.y)) as i32
+        pos::Pos2::from(*self).manhattan(&(*other).into()) as i32
+    }
+}
+
+/// The grid code keeps its own `i32` positions, which can step with
+/// `forward`; distances go through the shared type.
+impl From<Pos2> for pos::Pos2 {
+    fn from(Pos2 { y, x }: Pos2) -> pos::Pos2 {
+        pos::Pos2 {
+            y: y.into(),
+            x: x.into(),
+        }
     }
 }
 
@@ -32,6 +52,8 @@ enum Dir {
 }
 
 impl Dir {
+    const ALL: [Dir; 4] = [Dir::Up, Dir::Right, Dir::Down, Dir::Left];
+
     fn to_pos(self) -> Pos2 {
         match self {
             Dir::Up => Pos2 { y: -1, x: 0 },
@@ -59,14 +81,44 @@ struct Grid {
 }
 
 impl Grid {
-    fn from_input(input: &str) -> Grid {
+    /// Reads a rectangular grid of heat loss digits from 1 to 9; the
+    /// Manhattan heuristic relies on every tile costing at least 1. Lines and
+    /// columns in errors are 1-based.
+    fn from_input(input: &str) -> Result<Grid, ParseError> {
         let lines: Vec<_> = input.lines().collect();
+        let width = lines.first().map_or(0, |line| line.len());
+        if width == 0 {
+            return Err(ParseError("empty grid".to_string()));
+        }
 
-        Grid {
+        for (row, line) in lines.iter().enumerate() {
+            if line.len() != width {
+                return Err(ParseError(format!(
+                    "line {}: expected {} columns, found {}",
+                    row + 1,
+                    width,
+                    line.len()
+                )));
+            }
+            if let Some((col, c)) = line
+                .chars()
+                .enumerate()
+                .find(|(_, c)| !('1'..='9').contains(c))
+            {
+                return Err(ParseError(format!(
+                    "line {}, column {}: expected a digit from 1 to 9, found {:?}",
+                    row + 1,
+                    col + 1,
+                    c
+                )));
+            }
+        }
+
+        Ok(Grid {
             height: lines.len() as i32,
-            width: lines[0].len() as i32,
+            width: width as i32,
             contents: lines.concat().into_bytes(),
-        }
+        })
     }
 }
 
@@ -92,7 +144,7 @@ impl Grid {
 
 type HeatLoss = i32;
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
 struct Node {
     pos: Pos2,
     dir: Dir,
@@ -106,156 +158,518 @@ where
 {
     grid: &'a Grid,
     visited: HashMap<T::Rep, HeatLoss>,
+    best: HashMap<T::Rep, (Node, Option<T::Rep>)>,
     queue: PriorityQueue<T, HeatLoss>,
+    goals: Goals,
+    heuristic: Option<Heuristic>,
+    stats: SearchStats,
+}
+
+/// The tiles a route may end on.
+#[derive(Debug, Clone)]
+struct Goals {
+    width: i32,
+    tiles: Vec<bool>,
+}
+
+impl Goals {
+    /// The bottom right tile, as in the puzzle.
+    fn corner(grid: &Grid) -> Goals {
+        Goals::parse(grid, &[]).unwrap()
+    }
+
+    /// Accepts `x,y` tiles and the `right-edge` and `bottom-edge` shorthands,
+    /// defaulting to the bottom right corner if `specs` is empty.
+    fn parse(grid: &Grid, specs: &[&str]) -> Result<Goals, String> {
+        let mut goals = Goals {
+            width: grid.width,
+            tiles: vec![false; grid.contents.len()],
+        };
+        let mut add = |pos: Pos2| {
+            goals.tiles[(pos.y * grid.width + pos.x) as usize] = true;
+        };
+
+        if specs.is_empty() {
+            add(Pos2 {
+                x: grid.width - 1,
+                y: grid.height - 1,
+            });
+        }
+        for spec in specs {
+            match *spec {
+                "right-edge" => (0..grid.height).for_each(|y| {
+                    add(Pos2 {
+                        x: grid.width - 1,
+                        y,
+                    })
+                }),
+                "bottom-edge" => (0..grid.width).for_each(|x| {
+                    add(Pos2 {
+                        x,
+                        y: grid.height - 1,
+                    })
+                }),
+                spec => add(parse_pos(grid, spec)?),
+            }
+        }
+
+        Ok(goals)
+    }
+
+    fn contains(&self, pos: &Pos2) -> bool {
+        self.tiles[(pos.y * self.width + pos.x) as usize]
+    }
+
+    fn positions(&self) -> Vec<Pos2> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, goal)| **goal)
+            .map(|(ix, _)| Pos2 {
+                y: ix as i32 / self.width,
+                x: ix as i32 % self.width,
+            })
+            .collect()
+    }
+}
+
+fn parse_pos(grid: &Grid, s: &str) -> Result<Pos2, String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected x,y, got: {}", s))?;
+    let pos = Pos2 {
+        x: x.parse().map_err(|_| format!("invalid x in: {}", s))?,
+        y: y.parse().map_err(|_| format!("invalid y in: {}", s))?,
+    };
+    if pos.x < 0 || pos.x >= grid.width || pos.y < 0 || pos.y >= grid.height {
+        return Err(format!("{} is outside the grid", s));
+    }
+    Ok(pos)
+}
+
+/// A lower bound on the heat loss still to come from a tile to the nearest
+/// goal.
+#[derive(Clone)]
+enum Heuristic {
+    /// Plain Dijkstra.
+    Zero,
+    /// Every tile left to enter costs at least 1.
+    Manhattan(Vec<Pos2>),
+    /// The cheapest path from each tile to a goal ignoring the run
+    /// constraints, found with a Dijkstra backwards from the goals.
+    Reverse {
+        width: i32,
+        remaining: Vec<HeatLoss>,
+    },
+}
+
+impl Heuristic {
+    fn reverse(grid: &Grid, goals: &Goals) -> Heuristic {
+        let mut remaining = vec![HeatLoss::MAX; grid.contents.len()];
+        let mut queue = BinaryHeap::new();
+        for goal in goals.positions() {
+            remaining[(goal.y * grid.width + goal.x) as usize] = 0;
+            queue.push(Reverse((0, goal)));
+        }
+
+        while let Some(Reverse((heat_loss, pos))) = queue.pop() {
+            if heat_loss > remaining[(pos.y * grid.width + pos.x) as usize] {
+                continue;
+            }
+            // Stepping from a neighbour onto `pos` costs the heat of `pos`.
+            let heat_loss = heat_loss + (grid.get(&pos) - b'0') as HeatLoss;
+            for dir in Dir::ALL {
+                let prev = pos.forward(dir);
+                if prev.x < 0 || prev.x >= grid.width || prev.y < 0 || prev.y >= grid.height {
+                    continue;
+                }
+                let ix = (prev.y * grid.width + prev.x) as usize;
+                if heat_loss < remaining[ix] {
+                    remaining[ix] = heat_loss;
+                    queue.push(Reverse((heat_loss, prev)));
+                }
+            }
+        }
+
+        Heuristic::Reverse {
+            width: grid.width,
+            remaining,
+        }
+    }
+
+    fn estimate(&self, pos: &Pos2) -> HeatLoss {
+        match self {
+            Heuristic::Zero => 0,
+            Heuristic::Manhattan(targets) => targets
+                .iter()
+                .map(|target| pos.manhattan(target))
+                .min()
+                .unwrap_or(0),
+            Heuristic::Reverse { width, remaining } => remaining[(pos.y * width + pos.x) as usize],
+        }
+    }
+}
+
+/// Counters filled in by a search: nodes whose successors were generated,
+/// nodes discarded because a cheaper way to the same state was known, and
+/// nodes left in the queue when the goal was reached.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SearchStats {
+    expanded: usize,
+    pruned: usize,
+    queued: usize,
 }
 
 impl<'a, T: SearchNode + Hash + Eq> Search<'a, T> {
+    /// A search from `init` to the bottom right corner, guided by the
+    /// Manhattan distance unless another heuristic is given.
     fn new(grid: &'a Grid, init: T) -> Self {
-        let mut search = Search {
+        Search {
             grid,
             visited: HashMap::new(),
+            best: HashMap::new(),
             queue: PriorityQueue::new(),
-        };
-        search.queue.push(init, 0);
-        search
+            goals: Goals::corner(grid),
+            heuristic: None,
+            stats: SearchStats::default(),
+        }
+        .with_start(init)
+    }
+
+    fn with_start(mut self, init: T) -> Self {
+        self.best.insert(init.rep(), (*init.node(), None));
+        self.queue.push(init, 0);
+        self
+    }
+
+    fn with_goals(mut self, goals: Goals) -> Self {
+        self.goals = goals;
+        self
     }
-    fn search(mut self) -> Option<T> {
-        let target_pos = Pos2 {
-            x: self.grid.width - 1,
-            y: self.grid.height - 1,
-        };
 
-        let mut processed = 0;
-        let mut pruned = 0;
+    fn with_heuristic(mut self, heuristic: Heuristic) -> Self {
+        self.heuristic = Some(heuristic);
+        self
+    }
+
+    fn search(&mut self) -> Option<Route> {
+        let heuristic = self
+            .heuristic
+            .take()
+            .unwrap_or_else(|| Heuristic::Manhattan(self.goals.positions()));
 
         while let Some((node, _)) = self.queue.pop() {
-            processed += 1;
-            if node.is_legally_at_goal(&target_pos) {
-                println!(
-                    "{} processed, {} pruned, {} still in queue",
-                    processed,
-                    pruned,
-                    self.queue.len()
-                );
-                return Some(node);
+            if node.is_legally_at_goal(&self.goals) {
+                self.stats.queued = self.queue.len();
+                return Some(self.route(node.rep()));
             }
 
             if let Some(prior_heat_loss) = self.visited.get(&node.rep()) {
                 if *prior_heat_loss < node.heat_loss() {
-                    pruned += 1;
+                    self.stats.pruned += 1;
                     continue;
                 }
             }
 
             self.visited.insert(node.rep(), node.heat_loss());
+            self.stats.expanded += 1;
 
-            for node in node.next(self.grid) {
-                let cost = node.cost(&target_pos);
-                self.queue.push(node, -cost);
+            for next in node.next(self.grid) {
+                if let Some((best, _)) = self.best.get(&next.rep()) {
+                    if best.heat_loss <= next.heat_loss() {
+                        self.stats.pruned += 1;
+                        continue;
+                    }
+                }
+                self.best
+                    .insert(next.rep(), (*next.node(), Some(node.rep())));
+                let cost = next.cost(&heuristic);
+                self.queue.push(next, -cost);
             }
         }
         None
     }
-}
 
-trait SearchNode {
-    type Rep: Hash + PartialEq + Eq;
+    fn route(&self, goal: T::Rep) -> Route {
+        let mut steps = vec![];
+        let mut rep = Some(goal);
+        while let Some((node, parent)) = rep.and_then(|rep| self.best.get(&rep)) {
+            steps.push(*node);
+            rep = parent.clone();
+        }
+        steps.reverse();
+        Route { steps }
+    }
+}
 
-    fn is_legally_at_goal(&self, pos: &Pos2) -> bool;
-    fn rep(&self) -> Self::Rep;
-    fn heat_loss(&self) -> i32;
-    fn cost(&self, target: &Pos2) -> i32;
-    fn next(&self, grid: &Grid) -> Vec<Self>
-    where
-        Self: Sized;
+/// Dial's algorithm for crucibles. Every step costs a single digit, so the
+/// frontier fits in ten buckets indexed by heat loss modulo ten, and the
+/// best heat loss per `(pos, dir, run)` state lives in a dense array.
+struct BucketSearch<'a> {
+    grid: &'a Grid,
+    crucible: Crucible,
+    heat_loss: Vec<HeatLoss>,
+    parent: Vec<Option<usize>>,
+    buckets: [Vec<usize>; 10],
+    goals: Goals,
+    stats: SearchStats,
 }
 
-#[derive(Hash, PartialEq, Eq)]
-struct NodePart1(Node);
+impl<'a> BucketSearch<'a> {
+    fn new(grid: &'a Grid, init: CrucibleNode) -> Self {
+        let states = grid.contents.len() * 4 * (init.crucible.max_run as usize + 1);
+        let search = BucketSearch {
+            grid,
+            crucible: init.crucible,
+            heat_loss: vec![HeatLoss::MAX; states],
+            parent: vec![None; states],
+            buckets: Default::default(),
+            goals: Goals::corner(grid),
+            stats: SearchStats::default(),
+        };
+        search.with_start(init)
+    }
 
-impl SearchNode for NodePart1 {
-    type Rep = (Pos2, Dir, u8);
+    fn with_start(mut self, init: CrucibleNode) -> Self {
+        let ix = self.index(&init.node);
+        self.heat_loss[ix] = 0;
+        self.buckets[0].push(ix);
+        self
+    }
 
-    fn is_legally_at_goal(&self, pos: &Pos2) -> bool {
-        self.0.pos == *pos
+    fn with_goals(mut self, goals: Goals) -> Self {
+        self.goals = goals;
+        self
     }
 
-    fn rep(&self) -> Self::Rep {
-        (self.0.pos, self.0.dir, self.0.moves_in_dir)
+    fn index(&self, node: &Node) -> usize {
+        let tile = (node.pos.y * self.grid.width + node.pos.x) as usize;
+        (tile * 4 + node.dir as usize) * (self.crucible.max_run as usize + 1)
+            + node.moves_in_dir as usize
     }
 
-    fn heat_loss(&self) -> i32 {
-        self.0.heat_loss
+    fn node(&self, ix: usize) -> Node {
+        let runs = self.crucible.max_run as usize + 1;
+        let tile = (ix / runs / 4) as i32;
+        Node {
+            pos: Pos2 {
+                y: tile / self.grid.width,
+                x: tile % self.grid.width,
+            },
+            dir: Dir::ALL[ix / runs % 4],
+            moves_in_dir: (ix % runs) as u8,
+            heat_loss: self.heat_loss[ix],
+        }
     }
 
-    fn cost(&self, target: &Pos2) -> i32 {
-        self.0.heat_loss + self.0.pos.manhattan(target)
+    fn search(&mut self) -> Option<Route> {
+        let mut queued = self.buckets[0].len();
+        let mut cost = 0;
+        while queued > 0 {
+            let bucket = cost as usize % self.buckets.len();
+            while let Some(ix) = self.buckets[bucket].pop() {
+                queued -= 1;
+                if self.heat_loss[ix] < cost {
+                    self.stats.pruned += 1;
+                    continue;
+                }
+
+                let node = CrucibleNode {
+                    crucible: self.crucible,
+                    node: self.node(ix),
+                };
+                if node.is_legally_at_goal(&self.goals) {
+                    self.stats.queued = queued;
+                    return Some(self.route(ix));
+                }
+                self.stats.expanded += 1;
+
+                for next in node.next(self.grid) {
+                    let next_ix = self.index(&next.node);
+                    if self.heat_loss[next_ix] <= next.node.heat_loss {
+                        self.stats.pruned += 1;
+                        continue;
+                    }
+                    self.heat_loss[next_ix] = next.node.heat_loss;
+                    self.parent[next_ix] = Some(ix);
+                    self.buckets[next.node.heat_loss as usize % self.buckets.len()].push(next_ix);
+                    queued += 1;
+                }
+            }
+            cost += 1;
+        }
+        None
     }
 
-    fn next(&self, grid: &Grid) -> Vec<Self> {
-        let mut result = vec![];
-        let node = &self.0;
+    fn route(&self, goal: usize) -> Route {
+        let mut steps = vec![];
+        let mut ix = Some(goal);
+        while let Some(current) = ix {
+            steps.push(self.node(current));
+            ix = self.parent[current];
+        }
+        steps.reverse();
+        Route { steps }
+    }
+}
 
-        for dir in [Dir::Up, Dir::Left, Dir::Down, Dir::Right] {
-            let new_pos = node.pos.forward(dir);
-            let moves_in_dir = if node.dir == dir {
-                node.moves_in_dir + 1
-            } else {
-                1
+/// The nodes visited from the start tile to the goal, with the heat loss
+/// accumulated up to and including each of them.
+struct Route {
+    steps: Vec<Node>,
+}
+
+impl Route {
+    fn heat_loss(&self) -> HeatLoss {
+        self.steps.last().map_or(0, |node| node.heat_loss)
+    }
+
+    /// Draws the route over the grid like the puzzle does, leaving the start
+    /// tile as is.
+    fn render(&self, grid: &Grid) -> String {
+        let mut contents = grid.contents.clone();
+        for node in self.steps.iter().skip(1) {
+            contents[(node.pos.y * grid.width + node.pos.x) as usize] = match node.dir {
+                Dir::Up => b'^',
+                Dir::Right => b'>',
+                Dir::Down => b'v',
+                Dir::Left => b'<',
             };
-            if node.dir.is_opposite(dir)
-                || new_pos.x < 0
-                || new_pos.x >= grid.width
-                || new_pos.y < 0
-                || new_pos.y >= grid.height
-                || moves_in_dir > 3
-            {
-                continue;
-            }
+        }
+        contents
+            .chunks(grid.width as usize)
+            .map(|row| String::from_utf8_lossy(row) + "\n")
+            .collect()
+    }
+
+    /// Whether the route passes over some tile more than once, which the
+    /// walks from `k_best_routes` may do.
+    fn revisits_tile(&self) -> bool {
+        !self.steps.iter().map(|node| node.pos).all_unique()
+    }
 
-            result.push(NodePart1(Node {
-                pos: new_pos,
-                dir,
-                moves_in_dir,
-                heat_loss: node.heat_loss + (grid.get(&new_pos) - b'0') as i32,
-            }));
+    fn rows(&self) -> String {
+        let mut out = String::from("x,y,dir,run,heat_loss\n");
+        for node in &self.steps {
+            out += &format!(
+                "{},{},{:?},{},{}\n",
+                node.pos.x, node.pos.y, node.dir, node.moves_in_dir, node.heat_loss
+            );
+        }
+        out
+    }
+}
+
+trait SearchNode {
+    type Rep: Hash + PartialEq + Eq + Clone;
+
+    fn node(&self) -> &Node;
+    fn is_legally_at_goal(&self, goals: &Goals) -> bool;
+    fn rep(&self) -> Self::Rep;
+    fn heat_loss(&self) -> i32;
+    fn cost(&self, heuristic: &Heuristic) -> i32;
+    fn next(&self, grid: &Grid) -> Vec<Self>
+    where
+        Self: Sized;
+}
+
+/// How far a crucible may travel in a straight line: at least `min_run`
+/// blocks before it can turn or stop at the goal, and at most `max_run`.
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
+struct Crucible {
+    min_run: u8,
+    max_run: u8,
+}
+
+impl Crucible {
+    const NORMAL: Crucible = Crucible {
+        min_run: 1,
+        max_run: 3,
+    };
+    const ULTRA: Crucible = Crucible {
+        min_run: 4,
+        max_run: 10,
+    };
+
+    fn new(min_run: u8, max_run: u8) -> Result<Crucible, String> {
+        if min_run == 0 || min_run > max_run {
+            return Err(format!(
+                "need 1 <= min_run <= max_run, got {}..{}",
+                min_run, max_run
+            ));
+        }
+        Ok(Crucible { min_run, max_run })
+    }
+
+    /// A crucible with the given run limits, where a missing `min_run` is
+    /// the normal crucible's and a missing `max_run` is the normal one's or
+    /// `min_run`, whichever is longer.
+    fn with_runs(min_run: Option<u8>, max_run: Option<u8>) -> Result<Crucible, String> {
+        let min_run = min_run.unwrap_or(Crucible::NORMAL.min_run);
+        let max_run = max_run.unwrap_or(min_run.max(Crucible::NORMAL.max_run));
+        Crucible::new(min_run, max_run)
+    }
+
+    /// A crucible standing on `pos` before its first move. It has no run
+    /// yet, so it may leave in any direction; `dir` is only a placeholder.
+    fn at_start(self, pos: Pos2) -> CrucibleNode {
+        CrucibleNode {
+            crucible: self,
+            node: Node {
+                pos,
+                dir: Dir::Right,
+                moves_in_dir: 0,
+                heat_loss: 0,
+            },
         }
-        result
     }
 }
 
 #[derive(Hash, PartialEq, Eq)]
-struct NodePart2(Node);
+struct CrucibleNode {
+    crucible: Crucible,
+    node: Node,
+}
 
-impl SearchNode for NodePart2 {
+impl SearchNode for CrucibleNode {
     type Rep = (Pos2, Dir, u8);
 
-    fn is_legally_at_goal(&self, pos: &Pos2) -> bool {
-        self.0.pos == *pos && self.0.moves_in_dir >= 4
+    fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// The goal only counts once the crucible has run `min_run` blocks into
+    /// it, unless it never had to move at all.
+    fn is_legally_at_goal(&self, goals: &Goals) -> bool {
+        goals.contains(&self.node.pos)
+            && (self.node.moves_in_dir == 0 || self.node.moves_in_dir >= self.crucible.min_run)
     }
 
     fn rep(&self) -> Self::Rep {
-        (self.0.pos, self.0.dir, self.0.moves_in_dir)
+        (self.node.pos, self.node.dir, self.node.moves_in_dir)
     }
 
     fn heat_loss(&self) -> i32 {
-        self.0.heat_loss
+        self.node.heat_loss
     }
 
-    fn cost(&self, target: &Pos2) -> i32 {
-        self.0.heat_loss + self.0.pos.manhattan(target)
+    fn cost(&self, heuristic: &Heuristic) -> i32 {
+        self.node.heat_loss + heuristic.estimate(&self.node.pos)
     }
 
     fn next(&self, grid: &Grid) -> Vec<Self> {
         let mut result = vec![];
-
-        let node = &self.0;
+        let node = &self.node;
+        let Crucible { min_run, max_run } = self.crucible;
+        let starting = node.moves_in_dir == 0;
 
         for dir in [Dir::Up, Dir::Left, Dir::Down, Dir::Right] {
             let pos = node.pos.forward(dir);
-            if node.dir != dir && node.moves_in_dir < 4
-                || node.dir == dir && node.moves_in_dir >= 10
-                || node.dir.is_opposite(dir)
+            let straight = !starting && node.dir == dir;
+            if !starting
+                && (node.dir.is_opposite(dir)
+                    || !straight && node.moves_in_dir < min_run
+                    || straight && node.moves_in_dir >= max_run)
                 || pos.x < 0
                 || pos.x >= grid.width
                 || pos.y < 0
@@ -263,50 +677,615 @@ impl SearchNode for NodePart2 {
             {
                 continue;
             }
-            let heat_loss = node.heat_loss + (grid.get(&pos) - b'0') as i32;
-            let moves_in_dir = if node.dir == dir {
-                node.moves_in_dir + 1
-            } else {
-                1
-            };
-            let node = NodePart2(Node {
-                pos,
-                dir,
-                moves_in_dir,
-                heat_loss,
+            let moves_in_dir = if straight { node.moves_in_dir + 1 } else { 1 };
+            result.push(CrucibleNode {
+                crucible: self.crucible,
+                node: Node {
+                    pos,
+                    dir,
+                    moves_in_dir,
+                    heat_loss: node.heat_loss + (grid.get(&pos) - b'0') as i32,
+                },
             });
-            result.push(node);
         }
 
         result
     }
 }
 
-fn main() {
+fn report(label: &str, grid: &Grid, route: Route, args: &[String]) {
+    if args.iter().any(|arg| arg == "--render") {
+        print!("{}", route.render(grid));
+    }
+    if args.iter().any(|arg| arg == "--route") {
+        print!("{}", route.rows());
+    }
+    println!("{}: {}", label, route.heat_loss());
+}
+
+/// The `k` cheapest routes from any of `inits` to any goal, cheapest first.
+/// Every state may be settled up to `k` times, which yields the k shortest
+/// walks through the state graph: no two routes make the same sequence of
+/// moves, though a route may pass over a tile more than once. Such routes
+/// are marked as revisiting a tile when printed. A route ends on the first
+/// goal it reaches.
+fn k_best_routes<T: SearchNode>(grid: &Grid, inits: Vec<T>, goals: &Goals, k: usize) -> Vec<Route> {
+    let mut arena: Vec<(T, Option<usize>)> = vec![];
+    let mut settled: HashMap<T::Rep, usize> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let mut routes = vec![];
+
+    for init in inits {
+        queue.push(Reverse((init.heat_loss(), arena.len())));
+        arena.push((init, None));
+    }
+
+    while let Some(Reverse((_, ix))) = queue.pop() {
+        let times = settled.entry(arena[ix].0.rep()).or_insert(0);
+        if *times >= k {
+            continue;
+        }
+        *times += 1;
+
+        if arena[ix].0.is_legally_at_goal(goals) {
+            let mut steps = vec![];
+            let mut current = Some(ix);
+            while let Some(c) = current {
+                steps.push(*arena[c].0.node());
+                current = arena[c].1;
+            }
+            steps.reverse();
+            routes.push(Route { steps });
+            if routes.len() == k {
+                break;
+            }
+            continue;
+        }
+
+        for next in arena[ix].0.next(grid) {
+            queue.push(Reverse((next.heat_loss(), arena.len())));
+            arena.push((next, Some(ix)));
+        }
+    }
+
+    routes
+}
+
+fn k_best_label(label: &str, rank: usize, route: &Route) -> String {
+    let note = if route.revisits_tile() {
+        " (revisits a tile)"
+    } else {
+        ""
+    };
+    format!("{} route {}{}", label, rank, note)
+}
+
+/// The exact heat loss from every crucible state to a goal it may stop on,
+/// found with a Dijkstra backwards from the goal states over the reversed
+/// moves. States that cannot reach a goal are left out.
+fn remaining_heat_loss(
+    grid: &Grid,
+    crucible: Crucible,
+    goals: &Goals,
+) -> HashMap<(Pos2, Dir, u8), HeatLoss> {
+    let mut predecessors: HashMap<_, Vec<_>> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let mut remaining = HashMap::new();
+
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            for (dir, moves_in_dir) in Dir::ALL.into_iter().cartesian_product(0..=crucible.max_run)
+            {
+                let state = CrucibleNode {
+                    crucible,
+                    node: Node {
+                        pos: Pos2 { y, x },
+                        dir,
+                        moves_in_dir,
+                        heat_loss: 0,
+                    },
+                };
+                for next in state.next(grid) {
+                    predecessors
+                        .entry(next.rep())
+                        .or_default()
+                        .push((state.rep(), next.heat_loss()));
+                }
+                if state.is_legally_at_goal(goals) {
+                    remaining.insert(state.rep(), 0);
+                    queue.push(Reverse((0, state.rep())));
+                }
+            }
+        }
+    }
+
+    while let Some(Reverse((heat_loss, rep))) = queue.pop() {
+        if heat_loss > remaining[&rep] {
+            continue;
+        }
+        for (prev, step) in predecessors.get(&rep).into_iter().flatten() {
+            let heat_loss = heat_loss + step;
+            if remaining.get(prev).is_none_or(|known| heat_loss < *known) {
+                remaining.insert(*prev, heat_loss);
+                queue.push(Reverse((heat_loss, *prev)));
+            }
+        }
+    }
+
+    remaining
+}
+
+/// Checks that `heuristic` never estimates more than the heat loss that is
+/// really left, from any state in `remaining`.
+fn check_admissible(
+    grid: &Grid,
+    remaining: &HashMap<(Pos2, Dir, u8), HeatLoss>,
+    heuristic: &Heuristic,
+) -> Result<(), String> {
+    match remaining
+        .iter()
+        .filter(|((pos, _, _), heat_loss)| heuristic.estimate(pos) > **heat_loss)
+        .min()
+    {
+        Some(((pos, dir, run), heat_loss)) => Err(format!(
+            "estimate {} at {:?} exceeds the remaining {} going {:?} after {} moves on\n{}",
+            heuristic.estimate(pos),
+            pos,
+            heat_loss,
+            dir,
+            run,
+            String::from_utf8_lossy(&grid.contents)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Solves random grids from random start tiles with plain Dijkstra, and
+/// checks that no heuristic overestimates the heat loss left from any state
+/// and that A* with each of them finds the same optimum.
+fn check_heuristics(grids: usize, seed: u64) -> Result<(), String> {
+    let mut rng = XorShift::new(seed);
+
+    for _ in 0..grids {
+        let height = 1 + rng.below(12) as i32;
+        let width = 1 + rng.below(12) as i32;
+        let contents = (0..width * height)
+            .map(|_| b'1' + rng.below(9) as u8)
+            .collect();
+        let grid = Grid {
+            height,
+            width,
+            contents,
+        };
+        let start = Pos2 {
+            x: rng.below(width as u64) as i32,
+            y: rng.below(height as u64) as i32,
+        };
+        let goals = Goals::corner(&grid);
+        let crucible = Crucible::new(1 + rng.below(4) as u8, 4 + rng.below(7) as u8)?;
+
+        let mut dijkstra =
+            Search::new(&grid, crucible.at_start(start)).with_heuristic(Heuristic::Zero);
+        let Some(optimum) = dijkstra.search().map(|route| route.heat_loss()) else {
+            continue;
+        };
+        let remaining = remaining_heat_loss(&grid, crucible, &goals);
+        if remaining.get(&crucible.at_start(start).rep()) != Some(&optimum) {
+            return Err(format!(
+                "Dijkstra found {}, the backwards search {:?}",
+                optimum,
+                remaining.get(&crucible.at_start(start).rep())
+            ));
+        }
+
+        for heuristic in [
+            Heuristic::Manhattan(goals.positions()),
+            Heuristic::reverse(&grid, &goals),
+        ] {
+            check_admissible(&grid, &remaining, &heuristic)?;
+            let mut search = Search::new(&grid, crucible.at_start(start)).with_heuristic(heuristic);
+            let heat_loss = search.search().map(|route| route.heat_loss());
+            if heat_loss != Some(optimum) {
+                return Err(format!("A* found {:?}, Dijkstra {}", heat_loss, optimum));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    if let Some(grids) = flag_value(&args, "--check-heuristics")? {
+        let grids = grids
+            .parse()
+            .map_err(|_| format!("invalid --check-heuristics: {}", grids))?;
+        check_heuristics(grids, 0x5eed)?;
+        println!("All heuristics admissible on {} random grids", grids);
+        return Ok(());
+    }
+
     let contents = fs::read_to_string("inputs/day17.txt").expect("Could not read input");
+    let grid = Grid::from_input(&contents)?;
+    let starts = match flag_values(&args, "--start")?[..] {
+        [] => vec![Pos2 { x: 0, y: 0 }],
+        ref specs => specs
+            .iter()
+            .map(|spec| parse_pos(&grid, spec).map_err(|e| format!("invalid --start: {}", e)))
+            .collect::<Result<_, _>>()?,
+    };
+    let goals = Goals::parse(&grid, &flag_values(&args, "--goal")?)
+        .map_err(|e| format!("invalid --goal: {}", e))?;
+    let k = match flag_value(&args, "--k")? {
+        None => 1,
+        Some(k) => k.parse().map_err(|_| format!("invalid --k: {}", k))?,
+    };
+    if k > 1 {
+        // The k best routes come from a search of their own.
+        if let Some(flag) = ["--queue", "--heuristic", "--stats"]
+            .into_iter()
+            .find(|flag| args.iter().any(|arg| arg == flag))
+        {
+            return Err(format!("{} cannot be combined with --k {}", flag, k).into());
+        }
+    }
+    let bucket = match flag_value(&args, "--queue")? {
+        None | Some("heap") => false,
+        Some("bucket") => true,
+        Some(queue) => return Err(format!("unknown queue: {}", queue).into()),
+    };
+    let heuristic = match flag_value(&args, "--heuristic")? {
+        // The bucket queue orders by heat loss alone.
+        Some(_) if bucket => {
+            return Err("--heuristic cannot be combined with --queue bucket".into())
+        }
+        None | Some("manhattan") => None,
+        Some("zero") => Some(Heuristic::Zero),
+        Some("reverse") => Some(Heuristic::reverse(&grid, &goals)),
+        Some(heuristic) => return Err(format!("unknown heuristic: {}", heuristic).into()),
+    };
+
+    let run = |flag| {
+        flag_value(&args, flag)?
+            .map(|n| {
+                n.parse::<u8>()
+                    .map_err(|_| format!("invalid {}: {}", flag, n))
+            })
+            .transpose()
+    };
+    let crucibles = match (run("--min-run")?, run("--max-run")?) {
+        (None, None) => vec![("Part 1", Crucible::NORMAL), ("Part 2", Crucible::ULTRA)],
+        (min_run, max_run) => vec![("Heat loss", Crucible::with_runs(min_run, max_run)?)],
+    };
+
+    for (label, crucible) in crucibles {
+        let inits = starts.iter().map(|start| crucible.at_start(*start));
+
+        if k > 1 {
+            let routes = k_best_routes(&grid, inits.collect(), &goals, k);
+            if routes.is_empty() {
+                println!("{}: no route", label);
+            }
+            for (ix, route) in routes.into_iter().enumerate() {
+                report(&k_best_label(label, ix + 1, &route), &grid, route, &args);
+            }
+            continue;
+        }
+
+        let (route, stats) = if bucket {
+            let mut search = inits
+                .skip(1)
+                .fold(
+                    BucketSearch::new(&grid, crucible.at_start(starts[0])),
+                    |search, init| search.with_start(init),
+                )
+                .with_goals(goals.clone());
+            (search.search(), search.stats)
+        } else {
+            let mut search = inits
+                .skip(1)
+                .fold(
+                    Search::new(&grid, crucible.at_start(starts[0])),
+                    |search, init| search.with_start(init),
+                )
+                .with_goals(goals.clone());
+            if let Some(heuristic) = &heuristic {
+                search = search.with_heuristic(heuristic.clone());
+            }
+            (search.search(), search.stats)
+        };
+        if args.iter().any(|arg| arg == "--stats") {
+            println!("{:?}", stats);
+        }
+        match route {
+            Some(route) => report(label, &grid, route, &args),
+            None => println!("{}: no route", label),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+2413432311323
+3215453535623
+3255245654254
+3446585845452
+4546657867536
+1438598798454
+4457876987766
+3637877979653
+4654967986887
+4564679986453
+1224686865563
+2546548887735
+4322674655533
+";
+
+    const START: Pos2 = Pos2 { x: 0, y: 0 };
+
+    /// Checks that consecutive steps are adjacent and that the heat loss
+    /// adds up.
+    fn assert_consistent(grid: &Grid, route: &Route) {
+        for (prev, node) in route.steps.iter().tuple_windows() {
+            assert_eq!(prev.pos.forward(node.dir), node.pos);
+            assert_eq!(
+                prev.heat_loss + (grid.get(&node.pos) - b'0') as i32,
+                node.heat_loss
+            );
+        }
+    }
+
+    #[test]
+    fn sample() {
+        let grid = Grid::from_input(SAMPLE).unwrap();
+
+        let route = Search::new(&grid, Crucible::NORMAL.at_start(START))
+            .search()
+            .unwrap();
+        assert_eq!(route.heat_loss(), 102);
+        assert_consistent(&grid, &route);
+        assert!(route.steps.iter().all(|node| node.moves_in_dir <= 3));
+
+        let route = Search::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .unwrap();
+        assert_eq!(route.heat_loss(), 94);
+        assert_consistent(&grid, &route);
+        assert_eq!(route.steps.last().unwrap().pos, Pos2 { x: 12, y: 12 });
+    }
+
+    #[test]
+    fn render_and_rows() {
+        let grid = Grid::from_input(
+            "111111111111\n999999999991\n999999999991\n999999999991\n999999999991\n",
+        )
+        .unwrap();
+        let route = Search::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .unwrap();
+        assert_eq!(route.heat_loss(), 71);
+        assert_eq!(
+            route.render(&grid),
+            "1>>>>>>>1111\n9999999v9991\n9999999v9991\n9999999v9991\n9999999v>>>>\n"
+        );
+        assert_eq!(
+            route.rows().lines().take(3).collect_vec(),
+            ["x,y,dir,run,heat_loss", "0,0,Right,0,0", "1,0,Right,1,1"]
+        );
+    }
+
+    #[test]
+    fn crucible_rules() {
+        assert!(Crucible::new(0, 3).is_err());
+        assert!(Crucible::new(4, 3).is_err());
+        assert_eq!(Crucible::new(4, 10), Ok(Crucible::ULTRA));
+        assert_eq!(Crucible::with_runs(None, None), Ok(Crucible::NORMAL));
+        assert_eq!(Crucible::with_runs(Some(4), None), Crucible::new(4, 4));
+        assert_eq!(Crucible::with_runs(Some(2), None), Crucible::new(2, 3));
+        assert_eq!(Crucible::with_runs(None, Some(10)), Crucible::new(1, 10));
+        assert!(Crucible::with_runs(None, Some(0)).is_err());
+        assert!(Crucible::with_runs(Some(5), Some(4)).is_err());
+
+        // Starting downwards is just as valid as starting to the right.
+        let grid = Grid::from_input("19999\n19999\n19999\n19999\n11111\n").unwrap();
+        let route = Search::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .unwrap();
+        assert_eq!(route.heat_loss(), 8);
+        assert_eq!(route.steps[1].dir, Dir::Down);
 
-    let grid = Grid::from_input(&contents);
-
-    let search = Search::new(
-        &grid,
-        NodePart1(Node {
-            pos: Pos2 { x: 0, y: 0 },
-            dir: Dir::Right,
-            moves_in_dir: 0,
-            heat_loss: 0,
-        }),
-    );
-
-    println!("Part 1: {}", search.search().unwrap().heat_loss());
-
-    let search = Search::new(
-        &grid,
-        NodePart2(Node {
-            pos: Pos2 { x: 0, y: 0 },
-            dir: Dir::Right,
-            moves_in_dir: 0,
-            heat_loss: 0,
-        }),
-    );
-    println!("Part 2: {}", search.search().unwrap().heat_loss())
+        // A single tile grid is solved without moving.
+        let grid = Grid::from_input("5\n").unwrap();
+        let route = Search::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .unwrap();
+        assert_eq!(route.heat_loss(), 0);
+
+        // The goal cannot be reached with a run shorter than `min_run`.
+        let grid = Grid::from_input("111\n").unwrap();
+        assert!(Search::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .is_none());
+    }
+
+    #[test]
+    fn bucket_queue_matches_heap() {
+        let grid = Grid::from_input(SAMPLE).unwrap();
+        for crucible in [
+            Crucible::NORMAL,
+            Crucible::ULTRA,
+            Crucible::new(2, 5).unwrap(),
+        ] {
+            let mut heap = Search::new(&grid, crucible.at_start(START));
+            let mut bucket = BucketSearch::new(&grid, crucible.at_start(START));
+            let (expected, route) = (heap.search().unwrap(), bucket.search().unwrap());
+            assert_eq!(route.heat_loss(), expected.heat_loss());
+            assert_consistent(&grid, &route);
+            assert!(bucket.stats.expanded > 0);
+        }
+
+        let grid = Grid::from_input("111\n").unwrap();
+        assert!(BucketSearch::new(&grid, Crucible::ULTRA.at_start(START))
+            .search()
+            .is_none());
+    }
+
+    #[test]
+    fn heuristics() {
+        let grid = Grid::from_input(SAMPLE).unwrap();
+        let goal = Pos2 { x: 12, y: 12 };
+        let goals = Goals::corner(&grid);
+        let reverse = Heuristic::reverse(&grid, &goals);
+        assert_eq!(reverse.estimate(&goal), 0);
+        assert_eq!(reverse.estimate(&Pos2 { x: 12, y: 11 }), 3);
+        assert!(reverse.estimate(&START) >= Heuristic::Manhattan(vec![goal]).estimate(&START));
+
+        for heuristic in [Heuristic::Zero, reverse] {
+            let mut search =
+                Search::new(&grid, Crucible::ULTRA.at_start(START)).with_heuristic(heuristic);
+            assert_eq!(search.search().unwrap().heat_loss(), 94);
+        }
+
+        check_heuristics(200, 17).unwrap();
+
+        // The exact remaining heat loss agrees with the search, and a
+        // heuristic aiming at the wrong corner is caught at the goal, even
+        // though it estimates nothing from the start.
+        let remaining = remaining_heat_loss(&grid, Crucible::ULTRA, &goals);
+        assert_eq!(remaining[&(START, Dir::Right, 0)], 94);
+        assert_eq!(remaining[&(goal, Dir::Down, 4)], 0);
+        assert!(!remaining.contains_key(&(goal, Dir::Down, 3)));
+        let wrong_corner = Heuristic::Manhattan(vec![START]);
+        assert_eq!(wrong_corner.estimate(&START), 0);
+        assert!(check_admissible(&grid, &remaining, &wrong_corner).is_err());
+        assert!(check_admissible(&grid, &remaining, &Heuristic::Zero).is_ok());
+    }
+
+    #[test]
+    fn start_and_goal_sets() {
+        let grid = Grid::from_input(SAMPLE).unwrap();
+        let goals = Goals::parse(&grid, &["right-edge"]).unwrap();
+        assert_eq!(goals.positions().len(), 13);
+        assert!(Goals::parse(&grid, &["13,0"]).is_err());
+        assert!(Goals::parse(&grid, &["12"]).is_err());
+
+        let mut search = Search::new(&grid, Crucible::NORMAL.at_start(START))
+            .with_goals(goals.clone())
+            .with_heuristic(Heuristic::reverse(&grid, &goals));
+        let route = search.search().unwrap();
+        assert_eq!(route.steps.last().unwrap().pos.x, 12);
+
+        let mut bucket =
+            BucketSearch::new(&grid, Crucible::NORMAL.at_start(START)).with_goals(goals.clone());
+        assert_eq!(bucket.search().unwrap().heat_loss(), route.heat_loss());
+
+        // A start next to the goal only has to enter the goal tile.
+        let next_to_goal = Pos2 { x: 11, y: 12 };
+        let mut search = Search::new(&grid, Crucible::NORMAL.at_start(START))
+            .with_start(Crucible::NORMAL.at_start(next_to_goal));
+        let route = search.search().unwrap();
+        assert_eq!(route.heat_loss(), 3);
+        assert_eq!(route.steps[0].pos, next_to_goal);
+    }
+
+    #[test]
+    fn k_best() {
+        let grid = Grid::from_input(SAMPLE).unwrap();
+        let routes = k_best_routes(
+            &grid,
+            vec![Crucible::NORMAL.at_start(START)],
+            &Goals::corner(&grid),
+            5,
+        );
+        assert_eq!(routes.len(), 5);
+        assert_eq!(routes[0].heat_loss(), 102);
+        assert!(routes
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| a.heat_loss() <= b.heat_loss()));
+        let distinct = routes
+            .iter()
+            .map(|route| route.steps.iter().map(|node| node.pos).collect_vec())
+            .unique()
+            .count();
+        assert_eq!(distinct, 5);
+        routes
+            .iter()
+            .for_each(|route| assert_consistent(&grid, route));
+
+        // On a 2x3 grid, the fifth best walk circles back over the start.
+        let grid = Grid::from_input("111\n111\n").unwrap();
+        let routes = k_best_routes(
+            &grid,
+            vec![Crucible::NORMAL.at_start(START)],
+            &Goals::corner(&grid),
+            5,
+        );
+        let labels = routes
+            .iter()
+            .enumerate()
+            .map(|(ix, route)| k_best_label("Part 1", ix + 1, route))
+            .collect_vec();
+        assert_eq!(
+            labels,
+            [
+                "Part 1 route 1",
+                "Part 1 route 2",
+                "Part 1 route 3",
+                "Part 1 route 4",
+                "Part 1 route 5 (revisits a tile)"
+            ]
+        );
+        assert_eq!(routes[4].heat_loss(), 7);
+
+        // No route carries on past a goal to end on another one.
+        let grid = Grid::from_input("1111\n1111\n1111\n").unwrap();
+        let goals = Goals::parse(&grid, &["right-edge"]).unwrap();
+        let routes = k_best_routes(&grid, vec![Crucible::NORMAL.at_start(START)], &goals, 10);
+        assert_eq!(routes.len(), 10);
+        for route in &routes {
+            let on_goal = route
+                .steps
+                .iter()
+                .positions(|node| goals.contains(&node.pos))
+                .collect_vec();
+            assert_eq!(on_goal, [route.steps.len() - 1]);
+        }
+
+        // There is only one way across a 1x3 grid.
+        let grid = Grid::from_input("123\n").unwrap();
+        let routes = k_best_routes(
+            &grid,
+            vec![Crucible::NORMAL.at_start(START)],
+            &Goals::corner(&grid),
+            3,
+        );
+        assert_eq!(routes.len(), 1);
+    }
+
+    #[test]
+    fn invalid_grids() {
+        let err = |input| Grid::from_input(input).err().map(|e| e.0);
+        assert_eq!(err(""), Some("empty grid".to_string()));
+        assert_eq!(
+            err("123\n45\n678\n"),
+            Some("line 2: expected 3 columns, found 2".to_string())
+        );
+        assert_eq!(
+            err("123\n4x6\n"),
+            Some("line 2, column 2: expected a digit from 1 to 9, found 'x'".to_string())
+        );
+        assert_eq!(
+            err("02
+12
+"),
+            Some("line 1, column 1: expected a digit from 1 to 9, found '0'".to_string())
+        );
+    }
 }
@@ -1,2 +1,6 @@
-
-pub mod string;
\ No newline at end of file
+pub mod args;
+pub mod error;
+pub mod math;
+pub mod pos;
+pub mod rng;
+pub mod string;
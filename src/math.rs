@@ -0,0 +1,53 @@
+pub fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+pub fn lcm(a: i64, b: i64) -> i64 {
+    a / gcd(a, b) * b
+}
+
+/// Returns `(g, x, y)` with `a * x + b * y == g == gcd(a, b)`.
+fn extended_gcd(a: i64, b: i64) -> (i64, i64, i64) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - a / b * y)
+    }
+}
+
+/// Combines `x ≡ a1 (mod m1)` and `x ≡ a2 (mod m2)` into `x ≡ a (mod m)`
+/// with `m = lcm(m1, m2)` and `0 <= a < m`, or `None` if the two contradict
+/// each other. The moduli need not be coprime.
+pub fn crt((a1, m1): (i64, i64), (a2, m2): (i64, i64)) -> Option<(i64, i64)> {
+    let g = gcd(m1, m2);
+    if (a2 - a1) % g != 0 {
+        return None;
+    }
+    let m = lcm(m1, m2);
+
+    // x = a1 + m1 * k, where m1 / g * k ≡ (a2 - a1) / g (mod m2 / g).
+    let (_, inverse, _) = extended_gcd(m1 / g, m2 / g);
+    let k = ((a2 - a1) / g) as i128 * inverse as i128 % (m2 / g) as i128;
+    let a = (a1 as i128 + m1 as i128 * k).rem_euclid(m as i128);
+
+    Some((a as i64, m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chinese_remainder() {
+        assert_eq!(crt((2, 3), (3, 5)), Some((8, 15)));
+        assert_eq!(crt((1, 4), (3, 6)), Some((9, 12)));
+        assert_eq!(crt((0, 4), (1, 6)), None);
+        assert_eq!(crt((5, 7), (5, 7)), Some((5, 7)));
+        assert_eq!(crt((-1, 10), (0, 1)), Some((9, 10)));
+    }
+}
@@ -0,0 +1,11 @@
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone, PartialOrd, Ord)]
+pub struct Pos2 {
+    pub y: i64,
+    pub x: i64,
+}
+
+impl Pos2 {
+    pub fn manhattan(&self, other: &Self) -> u64 {
+        self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
+    }
+}
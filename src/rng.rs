@@ -0,0 +1,30 @@
+/// Minimal xorshift generator, enough to make up test inputs.
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Xorshift stays at zero once there, so a zero seed counts as 1.
+    pub fn new(seed: u64) -> XorShift {
+        XorShift(seed.max(1))
+    }
+
+    /// The next number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_seed() {
+        let mut rng = XorShift::new(0);
+        let rolls: Vec<_> = (0..100).map(|_| rng.below(6)).collect();
+        assert!(rolls.iter().all(|roll| *roll < 6));
+        assert!(rolls.iter().any(|roll| *roll != rolls[0]));
+    }
+}